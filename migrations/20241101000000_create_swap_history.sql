@@ -0,0 +1,22 @@
+-- Baseline schema the service has been writing to; a no-op on existing deployments.
+CREATE TABLE IF NOT EXISTS swap_history (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    timestamp BIGINT NOT NULL,
+    date VARCHAR(10) NOT NULL,
+    time VARCHAR(8) NOT NULL,
+    tx_id VARCHAR(128) NOT NULL,
+    in_asset VARCHAR(128) NOT NULL,
+    in_amount DOUBLE NOT NULL,
+    in_amount_usd DOUBLE NOT NULL,
+    in_address VARCHAR(128) NOT NULL,
+    out_asset_1 VARCHAR(128) NOT NULL,
+    out_amount_1 DOUBLE NOT NULL,
+    out_amount_1_usd DOUBLE NOT NULL,
+    out_address_1 VARCHAR(128) NOT NULL,
+    out_asset_2 VARCHAR(128) NULL,
+    out_amount_2 DOUBLE NULL,
+    out_amount_2_usd DOUBLE NULL,
+    out_address_2 VARCHAR(128) NULL,
+    INDEX idx_swap_history_timestamp (timestamp),
+    INDEX idx_swap_history_tx_id (tx_id)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS ingestion_checkpoints (
+    source VARCHAR(64) NOT NULL,
+    job VARCHAR(32) NOT NULL,
+    token VARCHAR(64) NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (source, job)
+);
@@ -0,0 +1,10 @@
+-- Natural key for idempotent upserts: one row per inbound tx and swap leg
+ALTER TABLE swap_history ADD COLUMN leg_key VARCHAR(255) NOT NULL DEFAULT '';
+
+UPDATE swap_history SET leg_key = CONCAT(in_asset, '|', out_asset_1, '|', out_address_1);
+
+DELETE newer FROM swap_history newer
+JOIN swap_history older
+  ON newer.tx_id = older.tx_id AND newer.leg_key = older.leg_key AND newer.id > older.id;
+
+ALTER TABLE swap_history ADD UNIQUE KEY uniq_swap_history_leg (tx_id, leg_key);
@@ -0,0 +1,19 @@
+ALTER TABLE swap_history ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'success';
+
+-- Inbound txs Midgard still reports as pending, polled until they settle
+CREATE TABLE IF NOT EXISTS pending_swaps (
+    tx_id VARCHAR(128) NOT NULL PRIMARY KEY,
+    first_seen_at BIGINT NOT NULL,
+    last_checked_at BIGINT NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    INDEX idx_pending_swaps_last_checked (last_checked_at)
+);
+
+-- First time each status was observed for an inbound tx
+CREATE TABLE IF NOT EXISTS swap_status_history (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    tx_id VARCHAR(128) NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    observed_at BIGINT NOT NULL,
+    UNIQUE KEY uniq_swap_status_history (tx_id, status)
+);
@@ -0,0 +1,13 @@
+-- Fully refunded swaps deliver nothing, so the first out leg becomes optional
+ALTER TABLE swap_history
+    MODIFY out_asset_1 VARCHAR(128) NULL,
+    MODIFY out_amount_1 DOUBLE NULL,
+    MODIFY out_amount_1_usd DOUBLE NULL,
+    MODIFY out_address_1 VARCHAR(128) NULL,
+    ADD COLUMN outcome VARCHAR(16) NOT NULL DEFAULT 'success',
+    ADD COLUMN refund_asset VARCHAR(128) NULL,
+    ADD COLUMN refund_amount DOUBLE NULL,
+    ADD COLUMN refund_amount_usd DOUBLE NULL,
+    ADD COLUMN refund_address VARCHAR(128) NULL,
+    ADD COLUMN refund_reason VARCHAR(512) NULL,
+    ADD INDEX idx_swap_history_outcome (outcome);
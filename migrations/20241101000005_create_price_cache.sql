@@ -0,0 +1,8 @@
+-- Daily CoinGecko prices, fetched once per coin and day
+CREATE TABLE IF NOT EXISTS price_cache (
+    coin_id VARCHAR(128) NOT NULL,
+    price_date DATE NOT NULL,
+    price_usd DOUBLE NOT NULL,
+    fetched_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (coin_id, price_date)
+);
@@ -0,0 +1,8 @@
+-- CoinGecko ids resolved for each asset: curated registry hits, search guesses and admin overrides
+CREATE TABLE IF NOT EXISTS coin_id_mappings (
+    asset VARCHAR(128) NOT NULL PRIMARY KEY,
+    coin_id VARCHAR(128) NOT NULL,
+    source VARCHAR(16) NOT NULL,
+    discovered_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
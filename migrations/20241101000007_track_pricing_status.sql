@@ -0,0 +1,7 @@
+-- Swaps are stored even when a leg can't be priced, the repricing job fills the USD values in later
+ALTER TABLE swap_history
+    MODIFY in_amount_usd DOUBLE NULL,
+    ADD COLUMN pricing_status VARCHAR(16) NOT NULL DEFAULT 'priced',
+    ADD COLUMN pricing_attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN next_pricing_at BIGINT NULL,
+    ADD INDEX idx_swap_history_pricing (pricing_status, next_pricing_at);
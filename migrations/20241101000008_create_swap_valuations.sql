@@ -0,0 +1,15 @@
+-- Where every stored USD value came from, one row per valued leg of a swap row
+CREATE TABLE IF NOT EXISTS swap_valuations (
+    tx_id VARCHAR(128) NOT NULL,
+    leg_key VARCHAR(255) NOT NULL,
+    leg VARCHAR(16) NOT NULL,
+    asset VARCHAR(128) NOT NULL,
+    provider VARCHAR(32) NOT NULL,
+    coin_id VARCHAR(128) NULL,
+    unit_price_usd DOUBLE NOT NULL,
+    amount_usd DOUBLE NOT NULL,
+    price_timestamp BIGINT NOT NULL,
+    swap_timestamp BIGINT NOT NULL,
+    confidence VARCHAR(8) NOT NULL,
+    PRIMARY KEY (tx_id, leg_key, leg)
+);
@@ -0,0 +1,10 @@
+-- Leg values in the configured quote currencies, converted from USD at swap time
+CREATE TABLE IF NOT EXISTS swap_quote_values (
+    tx_id VARCHAR(128) NOT NULL,
+    leg_key VARCHAR(255) NOT NULL,
+    leg VARCHAR(16) NOT NULL,
+    currency VARCHAR(8) NOT NULL,
+    amount DOUBLE NOT NULL,
+    usd_rate DOUBLE NOT NULL,
+    PRIMARY KEY (tx_id, leg_key, leg, currency)
+);
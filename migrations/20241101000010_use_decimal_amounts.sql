@@ -0,0 +1,18 @@
+-- Amounts are exact: integer base units, USD to the cent, prices and rates to 12 decimals
+ALTER TABLE swap_history
+    MODIFY in_amount DECIMAL(28, 0) NOT NULL,
+    MODIFY in_amount_usd DECIMAL(28, 2) NULL,
+    MODIFY out_amount_1 DECIMAL(28, 0) NULL,
+    MODIFY out_amount_1_usd DECIMAL(28, 2) NULL,
+    MODIFY out_amount_2 DECIMAL(28, 0) NULL,
+    MODIFY out_amount_2_usd DECIMAL(28, 2) NULL,
+    MODIFY refund_amount DECIMAL(28, 0) NULL,
+    MODIFY refund_amount_usd DECIMAL(28, 2) NULL;
+
+ALTER TABLE swap_valuations
+    MODIFY unit_price_usd DECIMAL(28, 12) NOT NULL,
+    MODIFY amount_usd DECIMAL(28, 2) NOT NULL;
+
+ALTER TABLE swap_quote_values
+    MODIFY amount DECIMAL(28, 8) NOT NULL,
+    MODIFY usd_rate DECIMAL(28, 12) NOT NULL;
@@ -0,0 +1,4 @@
+-- Synth, trade and secured swaps are ingested alongside native ones and labelled by kind
+ALTER TABLE swap_history
+    ADD COLUMN asset_kind VARCHAR(16) NOT NULL DEFAULT 'native',
+    ADD INDEX idx_swap_history_asset_kind (asset_kind);
@@ -0,0 +1,11 @@
+-- Progress of streaming swaps, NULL for swaps executed in a single block
+ALTER TABLE swap_history
+    ADD COLUMN streaming_count INT NULL,
+    ADD COLUMN streaming_quantity INT NULL,
+    ADD COLUMN streaming_interval INT NULL,
+    ADD COLUMN streaming_last_height BIGINT NULL,
+    ADD COLUMN streaming_deposited_amount DECIMAL(28, 0) NULL,
+    ADD COLUMN streaming_in_amount DECIMAL(28, 0) NULL,
+    ADD COLUMN streaming_out_amount DECIMAL(28, 0) NULL,
+    ADD COLUMN streaming_fill_percent DECIMAL(5, 2) NULL,
+    ADD COLUMN streaming_duration_secs BIGINT NULL;
@@ -0,0 +1,10 @@
+-- Every out leg a refund paid, the refund columns of swap_history hold their total
+CREATE TABLE IF NOT EXISTS swap_refund_legs (
+    tx_id VARCHAR(128) NOT NULL,
+    leg_key VARCHAR(255) NOT NULL,
+    leg_index INT NOT NULL,
+    asset VARCHAR(128) NOT NULL,
+    amount DECIMAL(28, 0) NOT NULL,
+    address VARCHAR(128) NOT NULL,
+    PRIMARY KEY (tx_id, leg_key, leg_index)
+);
@@ -0,0 +1,2 @@
+-- Swap days are stored as dates, existing rows already hold YYYY-MM-DD
+ALTER TABLE swap_history MODIFY date DATE NOT NULL;
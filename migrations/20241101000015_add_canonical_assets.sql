@@ -0,0 +1,29 @@
+-- The asset columns keep their CHAIN.TICKER form, the full asset with its kind separator and
+-- contract goes in its own column. NULL on rows stored before.
+ALTER TABLE swap_history
+    ADD COLUMN in_asset_canonical VARCHAR(128) NULL,
+    ADD COLUMN out_asset_1_canonical VARCHAR(128) NULL,
+    ADD COLUMN out_asset_2_canonical VARCHAR(128) NULL,
+    ADD COLUMN refund_asset_canonical VARCHAR(128) NULL;
+
+-- Rows that already stored the full asset in the short columns get it moved over and the
+-- short form put back, as the old parser did: chain and ticker joined by a dot
+UPDATE swap_history
+SET in_asset_canonical = in_asset,
+    in_asset = REGEXP_REPLACE(in_asset, '^([A-Z0-9]+)[./~-]([A-Z0-9]+).*$', '$1.$2')
+WHERE in_asset NOT REGEXP '^[A-Z0-9]+[.][A-Z0-9]+$';
+
+UPDATE swap_history
+SET out_asset_1_canonical = out_asset_1,
+    out_asset_1 = REGEXP_REPLACE(out_asset_1, '^([A-Z0-9]+)[./~-]([A-Z0-9]+).*$', '$1.$2')
+WHERE out_asset_1 NOT REGEXP '^[A-Z0-9]+[.][A-Z0-9]+$';
+
+UPDATE swap_history
+SET out_asset_2_canonical = out_asset_2,
+    out_asset_2 = REGEXP_REPLACE(out_asset_2, '^([A-Z0-9]+)[./~-]([A-Z0-9]+).*$', '$1.$2')
+WHERE out_asset_2 NOT REGEXP '^[A-Z0-9]+[.][A-Z0-9]+$';
+
+UPDATE swap_history
+SET refund_asset_canonical = refund_asset,
+    refund_asset = REGEXP_REPLACE(refund_asset, '^([A-Z0-9]+)[./~-]([A-Z0-9]+).*$', '$1.$2')
+WHERE refund_asset NOT REGEXP '^[A-Z0-9]+[.][A-Z0-9]+$';
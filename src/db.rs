@@ -1,63 +1,922 @@
+use chrono::Utc;
 use dotenv::dotenv;
-use sqlx::{mysql::MySqlPool, Error as SqlxError};
-use std::{env, fmt};
+use sqlx::{
+    mysql::{MySql, MySqlPool},
+    query_builder::Separated,
+    Error as SqlxError, QueryBuilder, Transaction,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+};
 
 use crate::{
-    models::actions_model::SwapTransactionFromatted, routes::swap_history::OrderType,
-    utils::format_date_for_sql,
+    models::{
+        actions_model::{
+            PricingStatus, SwapQuoteValue, SwapRefundLeg, SwapStatus, SwapStatusChange,
+            SwapStatusUpdate, SwapTransactionFromatted, SwapValuation, UnpricedSwap,
+        },
+        checkpoint_model::{Checkpoint, IngestionJob},
+        coin_mapping_model::CoinMapping,
+    },
+    routes::swap_history::{OrderType, SwapFilters},
 };
 
+// Every column of a swap row except the leg_key, in the order bind_swap_record binds them
+const SWAP_COLUMNS: &str = "timestamp, date, time, tx_id, in_asset, in_amount, in_amount_usd, in_address, out_asset_1, out_amount_1, out_amount_1_usd, out_address_1, out_asset_2, out_amount_2, out_amount_2_usd, out_address_2, status, outcome, refund_asset, refund_amount, refund_amount_usd, refund_address, refund_reason, pricing_status, asset_kind, streaming_count, streaming_quantity, streaming_interval, streaming_last_height, streaming_deposited_amount, streaming_in_amount, streaming_out_amount, streaming_fill_percent, streaming_duration_secs, in_asset_canonical, out_asset_1_canonical, out_asset_2_canonical, refund_asset_canonical";
+
+// Columns of swap_quote_values, the first four are its primary key
+const QUOTE_VALUE_COLUMNS: &str = "tx_id, leg_key, leg, currency, amount, usd_rate";
+
+// Columns of swap_refund_legs, the first three are its primary key
+const REFUND_LEG_COLUMNS: &str = "tx_id, leg_key, leg_index, asset, amount, address";
+
+// Columns of swap_valuations, the first three are its primary key
+const VALUATION_COLUMNS: &str = "tx_id, leg_key, leg, asset, provider, coin_id, unit_price_usd, amount_usd, price_timestamp, swap_timestamp, confidence";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictMode {
+    Skip,
+    Overwrite,
+    IfMoreComplete,
+}
+
+impl ConflictMode {
+    // SWAP_CONFLICT_MODE: skip | overwrite | if_more_complete (default)
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        match env::var("SWAP_CONFLICT_MODE").as_deref() {
+            Ok("skip") => ConflictMode::Skip,
+            Ok("overwrite") => ConflictMode::Overwrite,
+            _ => ConflictMode::IfMoreComplete,
+        }
+    }
+
+    pub fn should_update(
+        &self,
+        existing: &SwapTransactionFromatted,
+        incoming: &SwapTransactionFromatted,
+    ) -> bool {
+        // A settled swap always replaces its pending copy, and is never replaced by one
+        match (
+            SwapStatus::is_settled(&existing.status),
+            SwapStatus::is_settled(&incoming.status),
+        ) {
+            (false, true) => return true,
+            (true, false) => return false,
+            _ => (),
+        }
+        match self {
+            ConflictMode::Skip => false,
+            ConflictMode::Overwrite => true,
+            ConflictMode::IfMoreComplete => incoming.completeness() > existing.completeness(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    Inserted,
+    Updated,
+    Skipped,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UpsertSummary {
+    pub inserted: u64,
+    pub updated: u64,
+    pub skipped: u64,
+}
+
+impl UpsertSummary {
+    pub fn record(&mut self, outcome: UpsertOutcome) {
+        match outcome {
+            UpsertOutcome::Inserted => self.inserted += 1,
+            UpsertOutcome::Updated => self.updated += 1,
+            UpsertOutcome::Skipped => self.skipped += 1,
+        }
+    }
+}
+
+fn bind_swap_record<'qb, 'args: 'qb>(
+    row: &mut Separated<'qb, 'args, MySql, &'static str>,
+    record: &SwapTransactionFromatted,
+) {
+    row.push_bind(record.timestamp)
+        .push_bind(record.date)
+        .push_bind(record.time.clone())
+        .push_bind(record.tx_id.clone())
+        .push_bind(record.in_asset.clone())
+        .push_bind(record.in_amount)
+        .push_bind(record.in_amount_usd)
+        .push_bind(record.in_address.clone())
+        .push_bind(record.out_asset_1.clone())
+        .push_bind(record.out_amount_1)
+        .push_bind(record.out_amount_1_usd)
+        .push_bind(record.out_address_1.clone())
+        .push_bind(record.out_asset_2.clone())
+        .push_bind(record.out_amount_2)
+        .push_bind(record.out_amount_2_usd)
+        .push_bind(record.out_address_2.clone())
+        .push_bind(record.status.clone())
+        .push_bind(record.outcome.clone())
+        .push_bind(record.refund_asset.clone())
+        .push_bind(record.refund_amount)
+        .push_bind(record.refund_amount_usd)
+        .push_bind(record.refund_address.clone())
+        .push_bind(record.refund_reason.clone())
+        .push_bind(record.pricing_status.clone())
+        .push_bind(record.asset_kind.clone())
+        .push_bind(record.streaming_count)
+        .push_bind(record.streaming_quantity)
+        .push_bind(record.streaming_interval)
+        .push_bind(record.streaming_last_height)
+        .push_bind(record.streaming_deposited_amount)
+        .push_bind(record.streaming_in_amount)
+        .push_bind(record.streaming_out_amount)
+        .push_bind(record.streaming_fill_percent)
+        .push_bind(record.streaming_duration_secs)
+        .push_bind(record.in_asset_canonical.clone())
+        .push_bind(record.out_asset_1_canonical.clone())
+        .push_bind(record.out_asset_2_canonical.clone())
+        .push_bind(record.refund_asset_canonical.clone());
+}
+
+#[derive(Debug, Default)]
+pub struct PagePlan<'a> {
+    pub inserts: Vec<&'a SwapTransactionFromatted>,
+    pub updates: Vec<&'a SwapTransactionFromatted>,
+    // Keys of pending rows replaced by a settled copy stored under its own key
+    pub replaced: Vec<(String, String)>,
+    pub summary: UpsertSummary,
+}
+
+// The stored row a swap is compared with: the one under its key, or otherwise the stored copy
+// of the same tx on the other side of settling. A pending swap has no out legs yet, so its
+// key changes once it settles. Every stored row is taken by one key at most, so when several
+// settled legs follow one pending row only the first replaces it and the rest are new.
+fn stored_copy<'e>(
+    existing: &'e HashMap<(String, String), SwapTransactionFromatted>,
+    consumed: &mut HashSet<(String, String)>,
+    key: &(String, String),
+    record: &SwapTransactionFromatted,
+) -> Option<&'e SwapTransactionFromatted> {
+    if let Some(row) = existing.get(key) {
+        consumed.insert(key.clone());
+        return Some(row);
+    }
+    let (stored_key, row) = existing.iter().find(|(stored_key, row)| {
+        row.tx_id == record.tx_id
+            && SwapStatus::is_settled(&row.status) != SwapStatus::is_settled(&record.status)
+            && !consumed.contains(*stored_key)
+    })?;
+    consumed.insert(stored_key.clone());
+    Some(row)
+}
+
+// Decides per swap whether it is new, replaces the stored row or is skipped. A swap repeated
+// within the page is resolved against its earlier copy exactly like against a stored row, and
+// only the copy that is finally written counts as inserted or updated.
+pub fn plan_page<'a>(
+    existing: &HashMap<(String, String), SwapTransactionFromatted>,
+    records: &'a [SwapTransactionFromatted],
+    mode: ConflictMode,
+) -> PagePlan<'a> {
+    let mut plan = PagePlan::default();
+    let mut planned: HashMap<(String, String), &'a SwapTransactionFromatted> = HashMap::new();
+    // The stored row each key is compared with, resolved on the key's first copy
+    let mut stored: HashMap<(String, String), Option<&SwapTransactionFromatted>> = HashMap::new();
+    let mut consumed = HashSet::new();
+
+    for record in records {
+        let key = (record.tx_id.clone(), record.leg_key());
+        let stored_row = *stored
+            .entry(key.clone())
+            .or_insert_with(|| stored_copy(existing, &mut consumed, &key, record));
+        let current = planned.get(&key).copied().or(stored_row);
+
+        if current.is_none_or(|current| mode.should_update(current, record)) {
+            planned.insert(key, record);
+        }
+    }
+
+    // Only the final copy of every key is written, earlier copies count as skipped
+    for record in records {
+        let key = (record.tx_id.clone(), record.leg_key());
+        let is_final = planned
+            .get(&key)
+            .is_some_and(|chosen| std::ptr::eq(*chosen, record));
+        let outcome = if !is_final {
+            UpsertOutcome::Skipped
+        } else if let Some(stored) = stored.get(&key).copied().flatten() {
+            let stored_key = (stored.tx_id.clone(), stored.leg_key());
+            if stored_key != key {
+                plan.replaced.push(stored_key);
+            }
+            plan.updates.push(record);
+            UpsertOutcome::Updated
+        } else {
+            plan.inserts.push(record);
+            UpsertOutcome::Inserted
+        };
+        plan.summary.record(outcome);
+    }
+
+    plan
+}
+
 #[derive(Clone)]
 pub struct MySQL {
     pub pool: MySqlPool,
+    pub conflict_mode: ConflictMode,
 }
 
 impl MySQL {
     pub async fn init() -> Result<Self, SqlxError> {
         dotenv().ok();
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let database_url = env::var("DATABASE_URL")
+            .map_err(|_| SqlxError::Configuration("DATABASE_URL must be set".into()))?;
         let pool = MySqlPool::connect(&database_url).await?;
         println!("Connected to MySQL");
-        Ok(MySQL { pool })
+        sqlx::migrate!().run(&pool).await?;
+        Ok(MySQL {
+            pool,
+            conflict_mode: ConflictMode::from_env(),
+        })
+    }
+
+    // Rows already stored for any of the page's swaps, keyed by (tx_id, leg_key)
+    async fn fetch_existing_records(
+        tx: &mut Transaction<'_, MySql>,
+        records: &[SwapTransactionFromatted],
+    ) -> Result<HashMap<(String, String), SwapTransactionFromatted>, SqlxError> {
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "SELECT {} FROM swap_history WHERE tx_id IN (",
+            SWAP_COLUMNS
+        ));
+        let mut tx_ids = query.separated(", ");
+        for record in records {
+            tx_ids.push_bind(record.tx_id.clone());
+        }
+        query.push(") FOR UPDATE");
+
+        let rows = query
+            .build_query_as::<SwapTransactionFromatted>()
+            .fetch_all(&mut **tx)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| ((row.tx_id.clone(), row.leg_key()), row))
+            .collect())
+    }
+
+    // One multi-row statement for the whole batch. Updates reuse the insert path and let the
+    // unique (tx_id, leg_key) key turn each row into an overwrite.
+    async fn write_records(
+        tx: &mut Transaction<'_, MySql>,
+        records: &[&SwapTransactionFromatted],
+        overwrite: bool,
+    ) -> Result<(), SqlxError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "INSERT INTO swap_history (leg_key, {}) ",
+            SWAP_COLUMNS
+        ));
+        query.push_values(records, |mut row, record| {
+            row.push_bind(record.leg_key());
+            bind_swap_record(&mut row, record);
+        });
+        if overwrite {
+            let assignments: Vec<String> = SWAP_COLUMNS
+                .split(", ")
+                .map(|column| format!("{0} = VALUES({0})", column))
+                .collect();
+            query.push(" ON DUPLICATE KEY UPDATE ");
+            query.push(assignments.join(", "));
+        }
+
+        query.build().execute(&mut **tx).await?;
+        Ok(())
+    }
+
+    // Provenance of the rows' USD values and their quote currency values, replacing what an
+    // earlier valuation stored
+    async fn write_valuations(
+        tx: &mut Transaction<'_, MySql>,
+        records: &[&SwapTransactionFromatted],
+    ) -> Result<(), SqlxError> {
+        let valuations: Vec<&SwapValuation> = records
+            .iter()
+            .flat_map(|record| record.valuations.iter())
+            .collect();
+        if valuations.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "INSERT INTO swap_valuations ({}) ",
+            VALUATION_COLUMNS
+        ));
+        query.push_values(valuations, |mut row, valuation| {
+            row.push_bind(valuation.tx_id.clone())
+                .push_bind(valuation.leg_key.clone())
+                .push_bind(valuation.leg.clone())
+                .push_bind(valuation.asset.clone())
+                .push_bind(valuation.provider.clone())
+                .push_bind(valuation.coin_id.clone())
+                .push_bind(valuation.unit_price_usd)
+                .push_bind(valuation.amount_usd)
+                .push_bind(valuation.price_timestamp)
+                .push_bind(valuation.swap_timestamp)
+                .push_bind(valuation.confidence.clone());
+        });
+        let assignments: Vec<String> = VALUATION_COLUMNS
+            .split(", ")
+            .skip(3)
+            .map(|column| format!("{0} = VALUES({0})", column))
+            .collect();
+        query.push(" ON DUPLICATE KEY UPDATE ");
+        query.push(assignments.join(", "));
+
+        query.build().execute(&mut **tx).await?;
+        Self::write_quote_values(tx, records).await
+    }
+
+    async fn write_quote_values(
+        tx: &mut Transaction<'_, MySql>,
+        records: &[&SwapTransactionFromatted],
+    ) -> Result<(), SqlxError> {
+        let values: Vec<&SwapQuoteValue> = records
+            .iter()
+            .flat_map(|record| record.quote_values.iter())
+            .collect();
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "INSERT INTO swap_quote_values ({}) ",
+            QUOTE_VALUE_COLUMNS
+        ));
+        query.push_values(values, |mut row, value| {
+            row.push_bind(value.tx_id.clone())
+                .push_bind(value.leg_key.clone())
+                .push_bind(value.leg.clone())
+                .push_bind(value.currency.clone())
+                .push_bind(value.amount)
+                .push_bind(value.usd_rate);
+        });
+        query.push(" ON DUPLICATE KEY UPDATE amount = VALUES(amount), usd_rate = VALUES(usd_rate)");
+
+        query.build().execute(&mut **tx).await?;
+        Ok(())
+    }
+
+    // Removes swap rows and everything stored alongside them, by (tx_id, leg_key)
+    async fn delete_rows(
+        tx: &mut Transaction<'_, MySql>,
+        keys: &[(String, String)],
+    ) -> Result<(), SqlxError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        for table in [
+            "swap_valuations",
+            "swap_quote_values",
+            "swap_refund_legs",
+            "swap_history",
+        ] {
+            let mut query = QueryBuilder::<MySql>::new(format!(
+                "DELETE FROM {} WHERE (tx_id, leg_key) IN (",
+                table
+            ));
+            let mut binds = query.separated(", ");
+            for (tx_id, leg_key) in keys {
+                binds.push("(");
+                binds.push_bind_unseparated(tx_id.clone());
+                binds.push_unseparated(", ");
+                binds.push_bind_unseparated(leg_key.clone());
+                binds.push_unseparated(")");
+            }
+            query.push(")");
+            query.build().execute(&mut **tx).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_refund_legs(
+        tx: &mut Transaction<'_, MySql>,
+        records: &[&SwapTransactionFromatted],
+    ) -> Result<(), SqlxError> {
+        let legs: Vec<&SwapRefundLeg> = records
+            .iter()
+            .flat_map(|record| record.refund_legs.iter())
+            .collect();
+        if legs.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "INSERT INTO swap_refund_legs ({}) ",
+            REFUND_LEG_COLUMNS
+        ));
+        query.push_values(legs, |mut row, leg| {
+            row.push_bind(leg.tx_id.clone())
+                .push_bind(leg.leg_key.clone())
+                .push_bind(leg.leg_index)
+                .push_bind(leg.asset.clone())
+                .push_bind(leg.amount)
+                .push_bind(leg.address.clone());
+        });
+        query.push(
+            " ON DUPLICATE KEY UPDATE asset = VALUES(asset), amount = VALUES(amount), address = VALUES(address)",
+        );
+
+        query.build().execute(&mut **tx).await?;
+        Ok(())
+    }
+
+    // Appends newly observed statuses to the history and keeps pending_swaps in sync: pending
+    // txs are queued for reconciliation, settled ones leave the queue
+    async fn record_statuses(
+        tx: &mut Transaction<'_, MySql>,
+        statuses: &[SwapStatusUpdate],
+    ) -> Result<(), SqlxError> {
+        if statuses.is_empty() {
+            return Ok(());
+        }
+        let now = Utc::now().timestamp();
+
+        let mut history = QueryBuilder::<MySql>::new(
+            "INSERT IGNORE INTO swap_status_history (tx_id, status, observed_at) ",
+        );
+        history.push_values(statuses, |mut row, update| {
+            row.push_bind(update.tx_id.clone())
+                .push_bind(update.status.as_str())
+                .push_bind(now);
+        });
+        history.build().execute(&mut **tx).await?;
+
+        let (pending, settled): (Vec<_>, Vec<_>) = statuses
+            .iter()
+            .partition(|update| update.status == SwapStatus::Pending);
+
+        if !pending.is_empty() {
+            let mut query = QueryBuilder::<MySql>::new(
+                "INSERT IGNORE INTO pending_swaps (tx_id, first_seen_at, last_checked_at) ",
+            );
+            query.push_values(&pending, |mut row, update| {
+                row.push_bind(update.tx_id.clone())
+                    .push_bind(now)
+                    .push_bind(now);
+            });
+            query.build().execute(&mut **tx).await?;
+        }
+
+        if !settled.is_empty() {
+            let mut query =
+                QueryBuilder::<MySql>::new("DELETE FROM pending_swaps WHERE tx_id IN (");
+            let mut tx_ids = query.separated(", ");
+            for update in &settled {
+                tx_ids.push_bind(update.tx_id.clone());
+            }
+            query.push(")");
+            query.build().execute(&mut **tx).await?;
+        }
+
+        Ok(())
+    }
+
+    // Persists a whole page and advances its checkpoint in a single transaction: either every
+    // row and the new cursor are committed, or nothing is
+    pub async fn store_page(
+        &self,
+        records: &[SwapTransactionFromatted],
+        statuses: &[SwapStatusUpdate],
+        checkpoint: Option<&Checkpoint<'_>>,
+    ) -> Result<UpsertSummary, SqlxError> {
+        let mut tx = self.pool.begin().await?;
+
+        let existing = if records.is_empty() {
+            HashMap::new()
+        } else {
+            Self::fetch_existing_records(&mut tx, records).await?
+        };
+        let plan = plan_page(&existing, records, self.conflict_mode);
+
+        Self::write_records(&mut tx, &plan.inserts, false).await?;
+        Self::write_records(&mut tx, &plan.updates, true).await?;
+        Self::write_valuations(&mut tx, &plan.inserts).await?;
+        Self::write_valuations(&mut tx, &plan.updates).await?;
+        Self::write_refund_legs(&mut tx, &plan.inserts).await?;
+        Self::write_refund_legs(&mut tx, &plan.updates).await?;
+        Self::delete_rows(&mut tx, &plan.replaced).await?;
+        Self::record_statuses(&mut tx, statuses).await?;
+        if let Some(checkpoint) = checkpoint {
+            Self::save_checkpoint(&mut tx, checkpoint).await?;
+        }
+        tx.commit().await?;
+
+        Ok(plan.summary)
+    }
+
+    // `date` is the YYYY-MM-DD day the price applies to
+    pub async fn fetch_cached_price(
+        &self,
+        coin_id: &str,
+        date: &str,
+    ) -> Result<Option<f64>, SqlxError> {
+        sqlx::query_scalar::<_, f64>(
+            "SELECT price_usd FROM price_cache WHERE coin_id = ? AND price_date = ?",
+        )
+        .bind(coin_id)
+        .bind(date)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn store_cached_price(
+        &self,
+        coin_id: &str,
+        date: &str,
+        price_usd: f64,
+    ) -> Result<(), SqlxError> {
+        sqlx::query(
+            r#"
+            INSERT INTO price_cache (coin_id, price_date, price_usd)
+            VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE price_usd = VALUES(price_usd)
+            "#,
+        )
+        .bind(coin_id)
+        .bind(date)
+        .bind(price_usd)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn fetch_coin_mappings(&self) -> Result<Vec<CoinMapping>, SqlxError> {
+        sqlx::query_as::<_, CoinMapping>(
+            "SELECT asset, coin_id, source, discovered_at, updated_at FROM coin_id_mappings ORDER BY asset",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // Keeps the first resolution of an asset, later lookups of the same asset are no-ops
+    pub async fn record_coin_mapping(
+        &self,
+        asset: &str,
+        coin_id: &str,
+        source: &str,
+    ) -> Result<(), SqlxError> {
+        let now = Utc::now().timestamp();
+        sqlx::query(
+            r#"
+            INSERT IGNORE INTO coin_id_mappings (asset, coin_id, source, discovered_at, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(asset)
+        .bind(coin_id)
+        .bind(source)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
     }
 
-    pub async fn insert_new_record(
+    pub async fn save_coin_mapping(
         &self,
-        record: SwapTransactionFromatted,
+        asset: &str,
+        coin_id: &str,
+        source: &str,
     ) -> Result<(), SqlxError> {
-        // Parsing and formatting the data
-        let date = format_date_for_sql(&record.date).unwrap();
-        let time = record.time.clone();
+        let now = Utc::now().timestamp();
+        sqlx::query(
+            r#"
+            INSERT INTO coin_id_mappings (asset, coin_id, source, discovered_at, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                coin_id = VALUES(coin_id),
+                source = VALUES(source),
+                updated_at = VALUES(updated_at)
+            "#,
+        )
+        .bind(asset)
+        .bind(coin_id)
+        .bind(source)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn fetch_checkpoint(
+        &self,
+        source: &str,
+        job: IngestionJob,
+    ) -> Result<Option<String>, SqlxError> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT token FROM ingestion_checkpoints WHERE source = ? AND job = ?",
+        )
+        .bind(source)
+        .bind(job.as_str())
+        .fetch_optional(&self.pool)
+        .await
+    }
 
-        // Executing the insert query
-        sqlx::query!(
+    async fn save_checkpoint(
+        tx: &mut Transaction<'_, MySql>,
+        checkpoint: &Checkpoint<'_>,
+    ) -> Result<(), SqlxError> {
+        sqlx::query(
             r#"
-            INSERT INTO swap_history (timestamp, date, time, tx_id, in_asset, in_amount, in_amount_usd, in_address, out_asset_1, out_amount_1, out_amount_1_usd, out_address_1, out_asset_2, out_amount_2, out_amount_2_usd, out_address_2)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO ingestion_checkpoints (source, job, token)
+            VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE token = VALUES(token)
             "#,
-            record.timestamp,
-            date,
-            time,
-            record.tx_id,
-            record.in_asset,
-            record.in_amount,
-            record.in_amount_usd,
-            record.in_address,
-            record.out_asset_1,
-            record.out_amount_1,
-            record.out_amount_1_usd,
-            record.out_address_1,
-            record.out_asset_2,
-            record.out_amount_2,
-            record.out_amount_2_usd,
-            record.out_address_2
         )
+        .bind(checkpoint.source)
+        .bind(checkpoint.job.as_str())
+        .bind(checkpoint.token)
+        .execute(&mut **tx)
+        .await?;
+        Ok(())
+    }
+
+    // Seeds a checkpoint without overwriting one another replica may have written meanwhile
+    pub async fn import_checkpoint(&self, checkpoint: &Checkpoint<'_>) -> Result<(), SqlxError> {
+        sqlx::query(
+            "INSERT IGNORE INTO ingestion_checkpoints (source, job, token) VALUES (?, ?, ?)",
+        )
+        .bind(checkpoint.source)
+        .bind(checkpoint.job.as_str())
+        .bind(checkpoint.token)
         .execute(&self.pool)
         .await?;
+        Ok(())
+    }
+
+    // Stops polling txs that are still pending after max_attempts checks and marks their rows
+    // abandoned. Returns the txs given up on.
+    pub async fn drop_stale_pending_swaps(
+        &self,
+        max_attempts: u32,
+    ) -> Result<Vec<String>, SqlxError> {
+        let mut tx = self.pool.begin().await?;
+        let stale = sqlx::query_scalar::<_, String>(
+            "SELECT tx_id FROM pending_swaps WHERE attempts >= ? FOR UPDATE",
+        )
+        .bind(max_attempts)
+        .fetch_all(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM pending_swaps WHERE attempts >= ?")
+            .bind(max_attempts)
+            .execute(&mut *tx)
+            .await?;
+
+        if !stale.is_empty() {
+            let abandoned = SwapStatus::Abandoned.as_str();
+            let mut query = QueryBuilder::<MySql>::new("UPDATE swap_history SET status = ");
+            query.push_bind(abandoned);
+            query.push(" WHERE status = ");
+            query.push_bind(SwapStatus::Pending.as_str());
+            query.push(" AND tx_id IN (");
+            let mut tx_ids = query.separated(", ");
+            for tx_id in &stale {
+                tx_ids.push_bind(tx_id.clone());
+            }
+            query.push(")");
+            query.build().execute(&mut *tx).await?;
+
+            let now = Utc::now().timestamp();
+            let mut history = QueryBuilder::<MySql>::new(
+                "INSERT IGNORE INTO swap_status_history (tx_id, status, observed_at) ",
+            );
+            history.push_values(&stale, |mut row, tx_id| {
+                row.push_bind(tx_id.clone())
+                    .push_bind(abandoned)
+                    .push_bind(now);
+            });
+            history.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(stale)
+    }
 
+    // Pending txs that have gone longest without a check
+    pub async fn fetch_pending_swaps(&self, limit: u64) -> Result<Vec<String>, SqlxError> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT tx_id FROM pending_swaps ORDER BY last_checked_at ASC LIMIT ?",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn mark_pending_checked(&self, tx_id: &str) -> Result<(), SqlxError> {
+        sqlx::query(
+            "UPDATE pending_swaps SET last_checked_at = ?, attempts = attempts + 1 WHERE tx_id = ?",
+        )
+        .bind(Utc::now().timestamp())
+        .bind(tx_id)
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
 
+    // Unpriced legs whose next repricing attempt is due, never tried ones first, with the
+    // valuations and quote currency values already stored for them
+    pub async fn fetch_unpriced_swaps(&self, limit: u64) -> Result<Vec<UnpricedSwap>, SqlxError> {
+        let query = format!(
+            r#"
+            SELECT {}, pricing_attempts
+            FROM swap_history
+            WHERE pricing_status = ? AND (next_pricing_at IS NULL OR next_pricing_at <= ?)
+            ORDER BY next_pricing_at ASC, timestamp ASC
+            LIMIT ?
+            "#,
+            SWAP_COLUMNS
+        );
+        let mut unpriced = sqlx::query_as::<_, UnpricedSwap>(&query)
+            .bind(PricingStatus::Unpriced.as_str())
+            .bind(Utc::now().timestamp())
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let tx_ids: Vec<String> = unpriced
+            .iter()
+            .map(|unpriced| unpriced.swap.tx_id.clone())
+            .collect();
+        for valuation in self.fetch_valuations(&tx_ids).await? {
+            if let Some(unpriced) = unpriced.iter_mut().find(|unpriced| {
+                unpriced.swap.tx_id == valuation.tx_id
+                    && unpriced.swap.leg_key() == valuation.leg_key
+            }) {
+                unpriced.swap.valuations.push(valuation);
+            }
+        }
+        for value in self.fetch_quote_values(&tx_ids, None).await? {
+            if let Some(unpriced) = unpriced.iter_mut().find(|unpriced| {
+                unpriced.swap.tx_id == value.tx_id && unpriced.swap.leg_key() == value.leg_key
+            }) {
+                unpriced.swap.quote_values.push(value);
+            }
+        }
+        Ok(unpriced)
+    }
+
+    // Records one repricing attempt with the leg's USD values after it and the provenance of
+    // the values it filled in
+    pub async fn save_repricing(
+        &self,
+        swap: &SwapTransactionFromatted,
+        status: PricingStatus,
+        next_pricing_at: Option<i64>,
+    ) -> Result<(), SqlxError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE swap_history
+            SET in_amount_usd = ?,
+                out_amount_1_usd = ?,
+                out_amount_2_usd = ?,
+                refund_amount_usd = ?,
+                pricing_status = ?,
+                pricing_attempts = pricing_attempts + 1,
+                next_pricing_at = ?
+            WHERE tx_id = ? AND leg_key = ?
+            "#,
+        )
+        .bind(swap.in_amount_usd)
+        .bind(swap.out_amount_1_usd)
+        .bind(swap.out_amount_2_usd)
+        .bind(swap.refund_amount_usd)
+        .bind(status.as_str())
+        .bind(next_pricing_at)
+        .bind(&swap.tx_id)
+        .bind(swap.leg_key())
+        .execute(&mut *tx)
+        .await?;
+        Self::write_valuations(&mut tx, &[swap]).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Postpones a tx whose swap couldn't be fetched, without counting it as a pricing attempt
+    pub async fn defer_repricing(
+        &self,
+        tx_id: &str,
+        next_pricing_at: i64,
+    ) -> Result<(), SqlxError> {
+        sqlx::query(
+            "UPDATE swap_history SET next_pricing_at = ? WHERE tx_id = ? AND pricing_status = ?",
+        )
+        .bind(next_pricing_at)
+        .bind(tx_id)
+        .bind(PricingStatus::Unpriced.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn fetch_valuations(&self, tx_ids: &[String]) -> Result<Vec<SwapValuation>, SqlxError> {
+        if tx_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "SELECT {} FROM swap_valuations WHERE tx_id IN (",
+            VALUATION_COLUMNS
+        ));
+        let mut binds = query.separated(", ");
+        for tx_id in tx_ids {
+            binds.push_bind(tx_id.clone());
+        }
+        query.push(")");
+        query
+            .build_query_as::<SwapValuation>()
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn fetch_refund_legs(&self, tx_ids: &[String]) -> Result<Vec<SwapRefundLeg>, SqlxError> {
+        if tx_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "SELECT {} FROM swap_refund_legs WHERE tx_id IN (",
+            REFUND_LEG_COLUMNS
+        ));
+        let mut binds = query.separated(", ");
+        for tx_id in tx_ids {
+            binds.push_bind(tx_id.clone());
+        }
+        query.push(") ORDER BY leg_index");
+        query
+            .build_query_as::<SwapRefundLeg>()
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    // Values in one currency, or every currency when none is given
+    async fn fetch_quote_values(
+        &self,
+        tx_ids: &[String],
+        currency: Option<&str>,
+    ) -> Result<Vec<SwapQuoteValue>, SqlxError> {
+        if tx_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query = QueryBuilder::<MySql>::new(format!(
+            "SELECT {} FROM swap_quote_values WHERE ",
+            QUOTE_VALUE_COLUMNS
+        ));
+        if let Some(currency) = currency {
+            query.push("currency = ");
+            query.push_bind(currency.to_string());
+            query.push(" AND ");
+        }
+        query.push("tx_id IN (");
+        let mut binds = query.separated(", ");
+        for tx_id in tx_ids {
+            binds.push_bind(tx_id.clone());
+        }
+        query.push(")");
+        query
+            .build_query_as::<SwapQuoteValue>()
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn fetch_status_history(
+        &self,
+        tx_ids: &[String],
+    ) -> Result<Vec<SwapStatusChange>, SqlxError> {
+        if tx_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query = QueryBuilder::<MySql>::new(
+            "SELECT tx_id, status, observed_at FROM swap_status_history WHERE tx_id IN (",
+        );
+        let mut binds = query.separated(", ");
+        for tx_id in tx_ids {
+            binds.push_bind(tx_id.clone());
+        }
+        query.push(") ORDER BY observed_at ASC, id ASC");
+        query
+            .build_query_as::<SwapStatusChange>()
+            .fetch_all(&self.pool)
+            .await
+    }
+
     pub async fn fetch_latest_timestamp(&self) -> Result<Option<i64>, SqlxError> {
         let result = sqlx::query_scalar!(
             r#"
@@ -76,38 +935,48 @@ impl MySQL {
         limit: u64,
         sort_by: String,
         offset: u64,
-        search: Option<String>,
-        date: Option<String>,
+        filters: SwapFilters,
     ) -> Result<Vec<SwapTransactionFromatted>, SqlxError> {
         let base_query = format!(
             r#"
-            SELECT timestamp, date, time, tx_id, in_asset, in_amount, in_amount_usd, in_address,
-                   out_asset_1, out_amount_1, out_amount_1_usd, out_address_1,
-                   out_asset_2, out_amount_2, out_amount_2_usd, out_address_2
+            SELECT {}
             FROM swap_history
             WHERE (1 = 1)
             {}
             {}
+            {}
+            {}
             ORDER BY {} {:?}
             LIMIT ? OFFSET ?
             "#,
-            if search.is_some() {
+            SWAP_COLUMNS,
+            if filters.search.is_some() {
                 "AND (tx_id LIKE ? OR in_address LIKE ? OR out_address_1 LIKE ? OR out_address_2 LIKE ?)"
             } else {
                 ""
             },
-            if let Some(_) = date {
+            if filters.date.is_some() {
                 "AND date = ?"
             } else {
                 ""
             },
+            if filters.outcome.is_some() {
+                "AND outcome = ?"
+            } else {
+                ""
+            },
+            if filters.asset_kind.is_some() {
+                "AND asset_kind = ?"
+            } else {
+                ""
+            },
             sort_by,
             order,
         );
 
         let mut query = sqlx::query_as::<_, SwapTransactionFromatted>(&base_query);
 
-        if let Some(search_term) = search {
+        if let Some(search_term) = filters.search {
             let search_pattern = format!("%{}%", search_term);
             query = query
                 .bind(search_pattern.clone())
@@ -116,12 +985,56 @@ impl MySQL {
                 .bind(search_pattern.clone());
         }
 
-        if let Some(date_value) = date {
+        if let Some(date_value) = filters.date {
             query = query.bind(date_value);
         }
+        if let Some(outcome) = filters.outcome {
+            query = query.bind(outcome.as_str());
+        }
+        if let Some(asset_kind) = filters.asset_kind {
+            query = query.bind(asset_kind.as_str());
+        }
         query = query.bind(limit as i64).bind(offset as i64);
 
-        let records = query.fetch_all(&self.pool).await?;
+        let mut records = query.fetch_all(&self.pool).await?;
+
+        let tx_ids: Vec<String> = records.iter().map(|record| record.tx_id.clone()).collect();
+        for change in self.fetch_status_history(&tx_ids).await? {
+            for record in records
+                .iter_mut()
+                .filter(|record| record.tx_id == change.tx_id)
+            {
+                record.status_history.push(change.clone());
+            }
+        }
+        for valuation in self.fetch_valuations(&tx_ids).await? {
+            if let Some(record) = records.iter_mut().find(|record| {
+                record.tx_id == valuation.tx_id && record.leg_key() == valuation.leg_key
+            }) {
+                record.valuations.push(valuation);
+            }
+        }
+        for leg in self.fetch_refund_legs(&tx_ids).await? {
+            if let Some(record) = records
+                .iter_mut()
+                .find(|record| record.tx_id == leg.tx_id && record.leg_key() == leg.leg_key)
+            {
+                record.refund_legs.push(leg);
+            }
+        }
+        if let Some(currency) = filters.currency {
+            for value in self
+                .fetch_quote_values(&tx_ids, Some(currency.as_str()))
+                .await?
+            {
+                if let Some(record) = records
+                    .iter_mut()
+                    .find(|record| record.tx_id == value.tx_id && record.leg_key() == value.leg_key)
+                {
+                    record.quote_values.push(value);
+                }
+            }
+        }
 
         Ok(records)
     }
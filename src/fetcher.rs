@@ -1,18 +1,50 @@
 use crate::db::MySQL;
-use crate::utils::midgard::MidGard;
+use crate::models::actions_model::{PricingStatus, SwapTransaction, UnpricedSwap};
+use crate::models::checkpoint_model::{Checkpoint, IngestionJob};
+use crate::utils::actions_source::ActionsSource;
+use crate::utils::read_next_page_token_from_file;
+use crate::utils::retry::RetryPolicy;
 use crate::utils::transaction_handler::{TransactionError, TransactionHandler}; // Use the custom error type
-use crate::utils::{read_next_page_token_from_file, write_next_page_token_to_file};
 use chrono::Utc;
+use std::collections::HashMap;
 
-pub async fn fetch_historical_data() -> Result<(), TransactionError> {
-    let mysql = MySQL::init().await.map_err(|e| {
-        TransactionError::DatabaseError(format!("Error connecting to MySQL: {:?}", e))
+// Resumes from the stored backfill cursor, importing the legacy token file the first time
+async fn load_backfill_checkpoint(mysql: &MySQL, source: &str) -> Result<String, TransactionError> {
+    if let Some(token) = mysql
+        .fetch_checkpoint(source, IngestionJob::Backfill)
+        .await?
+    {
+        return Ok(token);
+    }
+
+    let token = read_next_page_token_from_file().map_err(|e| {
+        TransactionError::FileError(format!("Error reading legacy next page token: {:?}", e))
     })?;
+    println!("Importing legacy backfill checkpoint: {}", &token);
+    mysql
+        .import_checkpoint(&Checkpoint {
+            source,
+            job: IngestionJob::Backfill,
+            token: &token,
+        })
+        .await?;
 
-    let mut next_page_token = read_next_page_token_from_file().unwrap_or_default();
+    // Another replica may have won the import race, its token is the one that counts
+    Ok(mysql
+        .fetch_checkpoint(source, IngestionJob::Backfill)
+        .await?
+        .unwrap_or(token))
+}
+
+pub async fn fetch_historical_data<S: ActionsSource>(
+    mysql: &MySQL,
+    source: &S,
+    handler: &TransactionHandler,
+) -> Result<(), TransactionError> {
+    let mut next_page_token = load_backfill_checkpoint(mysql, source.name()).await?;
 
     loop {
-        let resp = match MidGard::fetch_actions_with_nextpage(next_page_token.as_str()).await {
+        let resp = match source.fetch_next_page(next_page_token.as_str()).await {
             Ok(resp) => resp,
             Err(err) => {
                 println!("Error fetching actions data: {:?}. Retrying...", err);
@@ -26,18 +58,18 @@ pub async fn fetch_historical_data() -> Result<(), TransactionError> {
             break;
         }
 
-        let process_response =
-            TransactionHandler::process_and_insert_transaction(&mysql, &resp.actions).await;
+        let checkpoint = Checkpoint {
+            source: source.name(),
+            job: IngestionJob::Backfill,
+            token: &resp.meta.nextPageToken,
+        };
+        let process_response = handler
+            .process_and_insert_transaction(mysql, &resp.actions, Some(&checkpoint))
+            .await;
 
         match process_response {
             Ok(_) => {
                 next_page_token = resp.meta.nextPageToken.clone();
-                if let Err(e) = write_next_page_token_to_file(&next_page_token) {
-                    return Err(TransactionError::FileError(format!(
-                        "Error writing next page token to file: {:?}",
-                        e
-                    )));
-                }
                 println!("Updated next page token: {}", &next_page_token);
             }
             Err(err) => {
@@ -52,48 +84,77 @@ pub async fn fetch_historical_data() -> Result<(), TransactionError> {
     Ok(())
 }
 
-pub async fn fetch_latest_data(mysql: &MySQL) -> Result<(), TransactionError> {
-    let latest_timestamp = match mysql.fetch_latest_timestamp().await {
-        Ok(Some(timestamp)) => timestamp,
-        Ok(None) => Utc::now().timestamp() as i64,
-        Err(err) => {
-            return Err(TransactionError::DatabaseError(format!(
-                "Error fetching the latest timestamp: {:?}",
-                err
-            )));
-        }
-    };
+pub async fn fetch_latest_data<S: ActionsSource>(
+    mysql: &MySQL,
+    source: &S,
+    handler: &TransactionHandler,
+) -> Result<(), TransactionError> {
+    // Continue from the stored tail cursor, or from the newest stored swap on the first run
+    let mut resp = match mysql
+        .fetch_checkpoint(source.name(), IngestionJob::Tail)
+        .await?
+    {
+        Some(prev_page_token) => match source.fetch_prev_page(&prev_page_token).await {
+            Ok(response) => response,
+            Err(err) => {
+                return Err(TransactionError::ApiError(format!(
+                    "Error fetching previous page actions: {:?}",
+                    err
+                )));
+            }
+        },
+        None => {
+            let latest_timestamp = match mysql.fetch_latest_timestamp().await {
+                Ok(Some(timestamp)) => timestamp,
+                Ok(None) => Utc::now().timestamp(),
+                Err(err) => {
+                    return Err(TransactionError::DatabaseError(format!(
+                        "Error fetching the latest timestamp: {:?}",
+                        err
+                    )));
+                }
+            };
 
-    let mysql_clone = mysql.clone();
-    let latest_timestamp_str = latest_timestamp.to_string();
-
-    // Fetch actions with the latest timestamp
-    let mut resp = match MidGard::fetch_actions_with_timestamp(&latest_timestamp_str).await {
-        Ok(response) => response,
-        Err(err) => {
-            return Err(TransactionError::ApiError(format!(
-                "Error fetching actions with timestamp: {:?}",
-                err
-            )));
-        }
-    };
-    let mut actions = resp.actions.clone();
-    actions.reverse();
-    let process_response =
-        TransactionHandler::process_and_insert_transaction(&mysql_clone, &actions).await;
-    match process_response {
-        Ok(_) => (),
-        Err(err) => {
-            return Err(TransactionError::ProcessingError(format!(
-                "Error processing transaction: {:?}",
-                err
-            )));
+            match source
+                .fetch_from_timestamp(&latest_timestamp.to_string())
+                .await
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    return Err(TransactionError::ApiError(format!(
+                        "Error fetching actions with timestamp: {:?}",
+                        err
+                    )));
+                }
+            }
         }
     };
 
     while !resp.actions.is_empty() {
+        // Pages come newest first, store them oldest first
+        let mut actions = resp.actions.clone();
+        actions.reverse();
+
+        let checkpoint = Checkpoint {
+            source: source.name(),
+            job: IngestionJob::Tail,
+            token: &resp.meta.prevPageToken,
+        };
+        let process_response = handler
+            .process_and_insert_transaction(mysql, &actions, Some(&checkpoint))
+            .await;
+        match process_response {
+            Ok(_) => (),
+            Err(err) => {
+                return Err(TransactionError::ProcessingError(format!(
+                    "Error processing transaction: {:?}",
+                    err
+                )));
+            }
+        };
+
         let prev_page_token = resp.meta.prevPageToken.clone();
-        resp = match MidGard::fetch_actions_with_prevpage(prev_page_token.as_str()).await {
+        resp = match source.fetch_prev_page(prev_page_token.as_str()).await {
             Ok(response) => response,
             Err(err) => {
                 return Err(TransactionError::ApiError(format!(
@@ -102,20 +163,136 @@ pub async fn fetch_latest_data(mysql: &MySQL) -> Result<(), TransactionError> {
                 )));
             }
         };
+    }
 
-        let process_response =
-            TransactionHandler::process_and_insert_transaction(&mysql_clone, &resp.actions).await;
-        match process_response {
-            Ok(_) => (),
+    println!("Latest Data Updated at : {}", Utc::now().timestamp());
+    Ok(())
+}
+
+// Re-queries swaps Midgard last reported as pending. Settled ones are stored with their final
+// status and leave the queue, the rest are checked again on a later run.
+pub async fn reconcile_pending_swaps<S: ActionsSource>(
+    mysql: &MySQL,
+    source: &S,
+    handler: &TransactionHandler,
+    batch_size: u64,
+    max_attempts: u32,
+) -> Result<(), TransactionError> {
+    for tx_id in mysql.drop_stale_pending_swaps(max_attempts).await? {
+        println!(
+            "Giving up on pending swap {} after {} checks, marked abandoned",
+            tx_id, max_attempts
+        );
+    }
+    let pending = mysql.fetch_pending_swaps(batch_size).await?;
+
+    for tx_id in pending {
+        let resp = match source.fetch_by_tx_id(&tx_id).await {
+            Ok(resp) => resp,
             Err(err) => {
-                return Err(TransactionError::ProcessingError(format!(
-                    "Error processing transaction: {:?}",
-                    err
-                )));
+                println!("Error fetching pending swap {}: {:?}", tx_id, err);
+                continue;
+            }
+        };
+        mysql.mark_pending_checked(&tx_id).await?;
+
+        let actions: Vec<SwapTransaction> = resp
+            .actions
+            .into_iter()
+            .filter(|action| {
+                action
+                    .in_data
+                    .iter()
+                    .any(|data| data.txID.as_deref() == Some(tx_id.as_str()))
+            })
+            .collect();
+        if actions.is_empty() {
+            continue;
+        }
+
+        handler
+            .process_and_insert_transaction(mysql, &actions, None)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Values stored legs that are missing USD or quote currency values. Each due tx is fetched
+// again so every price provider sees the same swap as at ingestion. Legs that still can't be
+// priced are retried with exponential backoff until the policy's attempts run out, then marked
+// failed.
+pub async fn reprice_swaps<S: ActionsSource>(
+    mysql: &MySQL,
+    source: &S,
+    handler: &TransactionHandler,
+    batch_size: u64,
+    policy: &RetryPolicy,
+) -> Result<(), TransactionError> {
+    let mut due: HashMap<String, Vec<UnpricedSwap>> = HashMap::new();
+    for unpriced in mysql.fetch_unpriced_swaps(batch_size).await? {
+        due.entry(unpriced.swap.tx_id.clone())
+            .or_default()
+            .push(unpriced);
+    }
+
+    for (tx_id, legs) in due {
+        let resp = match source.fetch_by_tx_id(&tx_id).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                println!("Error fetching swap {} for repricing: {:?}", tx_id, err);
+                let next_pricing_at = Utc::now().timestamp() + policy.base_delay.as_secs() as i64;
+                mysql.defer_repricing(&tx_id, next_pricing_at).await?;
+                continue;
             }
         };
+
+        let mut repriced = Vec::new();
+        for action in resp.actions.iter().filter(|action| {
+            action
+                .in_data
+                .iter()
+                .any(|data| data.txID.as_deref() == Some(tx_id.as_str()))
+        }) {
+            match handler.parse_transaction(action).await {
+                Ok(record) => repriced.push(record),
+                Err(err) => println!("Error repricing transaction {}: {:?}", tx_id, err),
+            }
+        }
+
+        for UnpricedSwap {
+            mut swap,
+            pricing_attempts,
+        } in legs
+        {
+            if let Some(record) = repriced
+                .iter()
+                .find(|record| record.leg_key() == swap.leg_key())
+            {
+                swap.fill_usd_values(record);
+            }
+
+            let attempts = pricing_attempts.max(0) as u32 + 1;
+            let (status, next_pricing_at) = match PricingStatus::of(&swap, handler.currencies()) {
+                PricingStatus::Priced => (PricingStatus::Priced, None),
+                _ if attempts >= policy.max_attempts => {
+                    println!(
+                        "Giving up pricing {} after {} attempts",
+                        swap.tx_id, attempts
+                    );
+                    (PricingStatus::Failed, None)
+                }
+                _ => {
+                    let delay = policy.delay_for(attempts, None);
+                    (
+                        PricingStatus::Unpriced,
+                        Some(Utc::now().timestamp() + delay.as_secs() as i64),
+                    )
+                }
+            };
+            mysql.save_repricing(&swap, status, next_pricing_at).await?;
+        }
     }
 
-    println!("Latest Data Updated at : {}", latest_timestamp_str);
     Ok(())
 }
@@ -8,7 +8,14 @@ use actix_cors::Cors;
 use actix_web::{get, web::Data, App, HttpResponse, HttpServer, Responder};
 use db::MySQL;
 use fetcher::fetch_historical_data;
-use utils::cron::start_cronjob;
+use std::sync::Arc;
+use utils::{
+    cron::{start_cronjob, start_reconciliation_job, start_repricing_job},
+    midgard::MidGard,
+    transaction_handler::{
+        coingecko_client, swap_asset_kinds_from_env, TransactionHandler, ValuationConfig,
+    },
+};
 
 #[get("/")]
 async fn home() -> impl Responder {
@@ -17,20 +24,54 @@ async fn home() -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    tokio::spawn(async move { fetch_historical_data().await });
-
+    // Configuration is checked here, before any job starts
+    let valuation = ValuationConfig::from_env().expect("Invalid valuation configuration");
+    let asset_kinds = swap_asset_kinds_from_env().expect("Invalid SWAP_ASSET_KINDS");
+    let midgard = MidGard::init().expect("Failed to initialize Midgard client");
     let mysql = MySQL::init().await.expect("Error COnnecting to SQL");
+    // Shares the server's pool for its price cache and coin mappings, only built when used
+    let coingecko = coingecko_client(&valuation, &mysql).expect("Invalid valuation configuration");
+    let handler = Arc::new(
+        TransactionHandler::new(valuation, asset_kinds, &midgard, coingecko.clone())
+            .expect("Failed to initialize price oracle"),
+    );
+
+    let backfill_mysql = mysql.clone();
+    let midgard_clone = midgard.clone();
+    let backfill_handler = handler.clone();
+    tokio::spawn(async move {
+        fetch_historical_data(&backfill_mysql, &midgard_clone, &backfill_handler).await
+    });
+
     let mysql_clone = mysql.clone();
-    tokio::spawn(async move { start_cronjob(mysql_clone).await });
+    let reconcile_mysql = mysql.clone();
+    let reconcile_midgard = midgard.clone();
+    let reprice_mysql = mysql.clone();
+    let reprice_midgard = midgard.clone();
+    let reconcile_handler = handler.clone();
+    let reprice_handler = handler.clone();
+    tokio::spawn(async move { start_cronjob(mysql_clone, midgard, handler).await });
+    tokio::spawn(async move {
+        start_reconciliation_job(reconcile_mysql, reconcile_midgard, reconcile_handler).await
+    });
+    tokio::spawn(async move {
+        start_repricing_job(reprice_mysql, reprice_midgard, reprice_handler).await
+    });
 
     // Create mysql_data for the Actix app
     let mysql_data = Data::new(mysql);
+    let coingecko_data = coingecko.map(Data::from);
     let server = HttpServer::new(move || {
-        App::new()
-            .app_data(mysql_data.clone())
-            .wrap(Cors::permissive())
+        let app = App::new().app_data(mysql_data.clone());
+        let app = match &coingecko_data {
+            Some(coingecko_data) => app.app_data(coingecko_data.clone()),
+            None => app,
+        };
+        app.wrap(Cors::permissive())
             .service(home)
             .configure(routes::swap_history::init)
+            .configure(routes::coin_mappings::init)
+            .configure(routes::metrics::init)
     })
     .bind(("0.0.0.0", 3000))
     .expect("Failed to bind Actix server")
@@ -1,7 +1,16 @@
 #![allow(non_snake_case)]
+use crate::utils::asset::{short_asset_name, underlying_asset, Asset, AssetKind};
+use crate::utils::currency::QuoteCurrency;
+use crate::utils::parse_amount;
+use chrono::NaiveDate;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
+pub const RUNE_ASSET: &str = "THOR.RUNE";
+// Average THORChain block time, used to turn block spans into durations
+pub const THORCHAIN_BLOCK_SECS: i64 = 6;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SwapCoin {
     pub amount: String,
@@ -19,10 +28,62 @@ pub struct TransactionData {
 pub struct TransactionMetaSwap {
     pub inPriceUSD: String,
     pub outPriceUSD: String,
+    #[serde(default)]
+    pub isStreamingSwap: bool,
+    pub streamingSwapMeta: Option<StreamingSwapMeta>,
+}
+// Progress of a streaming swap, which THORChain executes as `quantity` sub-swaps `interval`
+// blocks apart. Midgard reports counts and heights as strings, and leaves out whatever it
+// doesn't know yet, so every field is optional.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamingSwapMeta {
+    #[serde(default)]
+    pub count: Option<String>,
+    #[serde(default)]
+    pub quantity: Option<String>,
+    #[serde(default)]
+    pub interval: Option<String>,
+    #[serde(default)]
+    pub lastHeight: Option<String>,
+    #[serde(default)]
+    pub depositedCoin: Option<SwapCoin>,
+    #[serde(default)]
+    pub inCoin: Option<SwapCoin>,
+    #[serde(default)]
+    pub outCoin: Option<SwapCoin>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionMetaRefund {
+    #[serde(default)]
+    pub reason: String,
+    // Memo of the refunded inbound tx
+    #[serde(default)]
+    pub memo: String,
+    // Kind of the refunded tx, when Midgard reports it
+    #[serde(default)]
+    pub txType: Option<String>,
+}
+
+impl TransactionMetaRefund {
+    // Refund actions also cover failed deposits, bad memos and donations. Only the ones
+    // returning a swap are kept, from the memo's SWAP / s / = function or the reported kind.
+    pub fn is_swap_refund(&self) -> bool {
+        if let Some(tx_type) = &self.txType {
+            if tx_type.eq_ignore_ascii_case("swap") {
+                return true;
+            }
+        }
+        let function = self.memo.trim().split(':').next().unwrap_or_default();
+        ["swap", "s", "="]
+            .iter()
+            .any(|prefix| function.eq_ignore_ascii_case(prefix))
+    }
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TransactionMetaData {
-    pub swap: TransactionMetaSwap,
+    // Swap actions carry `swap`, refund actions carry `refund`
+    pub swap: Option<TransactionMetaSwap>,
+    pub refund: Option<TransactionMetaRefund>,
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SwapTransaction {
@@ -34,6 +95,356 @@ pub struct SwapTransaction {
     pub metadata: TransactionMetaData,
     pub pools: Vec<String>,
     pub status: String,
+    #[serde(rename = "type", default)]
+    pub action_type: String,
+    // Block the inbound tx was observed at
+    #[serde(default)]
+    pub height: String,
+}
+
+impl SwapTransaction {
+    // Swap actions, and refund actions that return a swap
+    pub fn is_swap(&self) -> bool {
+        if self.action_type != "refund" {
+            return true;
+        }
+        self.metadata
+            .refund
+            .as_ref()
+            .is_some_and(|refund| refund.is_swap_refund())
+    }
+
+    // Splits the out legs into delivered outputs and refunds. A refund action only returns
+    // funds, while a swap leg paying the inbound asset back to the sender is the refunded
+    // remainder of a partially filled streaming swap.
+    pub fn split_out_legs(&self) -> (Vec<&TransactionData>, Vec<&TransactionData>) {
+        let inbound = self.in_data.first();
+        let inbound_asset = inbound
+            .and_then(|data| data.coins.first())
+            .map(|coin| &coin.asset);
+
+        self.out_data.iter().rev().partition(|out| {
+            let is_refund = self.action_type == "refund"
+                || (inbound.map(|data| &data.address) == Some(&out.address)
+                    && out.coins.first().map(|coin| &coin.asset) == inbound_asset);
+            !is_refund
+        })
+    }
+
+    // L1 asset behind the inbound coin, the name its pool and prices are listed under
+    fn inbound_underlying(&self) -> Option<String> {
+        let inbound_asset = self.in_data.first()?.coins.first()?.asset.as_str();
+        Some(underlying_asset(inbound_asset))
+    }
+
+    // The asset the swap buys. The pool that isn't the inbound asset's, or RUNE when the
+    // inbound asset is sold into its own pool. Pools are L1 assets, so synth, trade and secured
+    // legs resolve to their underlying asset.
+    pub fn target_asset(&self) -> Option<&str> {
+        let inbound_asset = self.inbound_underlying()?;
+        match self.pools.iter().rev().find(|pool| **pool != inbound_asset) {
+            Some(pool) => Some(pool.as_str()),
+            None if !self.pools.is_empty() => Some(RUNE_ASSET),
+            None => None,
+        }
+    }
+
+    // USD price Midgard recorded for the L1 asset at the swap's block. Only the inbound and
+    // target assets are priced, and a zero price means Midgard had none.
+    pub fn midgard_price_usd(&self, asset: &str) -> Option<f64> {
+        let prices = self.metadata.swap.as_ref()?;
+        let price = if Some(asset) == self.inbound_underlying().as_deref() {
+            &prices.inPriceUSD
+        } else if Some(asset) == self.target_asset() {
+            &prices.outPriceUSD
+        } else {
+            return None;
+        };
+        price.parse::<f64>().ok().filter(|price| *price > 0.0)
+    }
+
+    // Streaming progress of the swap, None for swaps executed in a single block
+    pub fn streaming_details(&self) -> Option<StreamingSwapDetails> {
+        let swap = self.metadata.swap.as_ref()?;
+        let meta = swap
+            .streamingSwapMeta
+            .as_ref()
+            .filter(|_| swap.isStreamingSwap)?;
+
+        let number = |value: &Option<String>| value.as_deref()?.parse::<i64>().ok();
+        let amount = |coin: &Option<SwapCoin>| parse_amount(&coin.as_ref()?.amount).ok();
+        let count = number(&meta.count).and_then(|count| i32::try_from(count).ok());
+        let interval = number(&meta.interval).and_then(|interval| i32::try_from(interval).ok());
+        let last_height = number(&meta.lastHeight);
+        let deposited_amount = amount(&meta.depositedCoin);
+        let in_amount = amount(&meta.inCoin);
+
+        // Blocks between the inbound and the last sub-swap when the start height is known,
+        // otherwise the spacing of the sub-swaps executed so far. Unknown when the meta lacks
+        // both.
+        let start_height = self.height.parse::<i64>().ok().filter(|height| *height > 0);
+        let blocks = match (start_height, last_height, count, interval) {
+            (Some(height), Some(last_height), _, _) if last_height >= height => {
+                Some(last_height - height)
+            }
+            (_, _, Some(count), Some(interval)) => {
+                Some(i64::from((count - 1).max(0)) * i64::from(interval))
+            }
+            _ => None,
+        };
+
+        Some(StreamingSwapDetails {
+            count,
+            quantity: number(&meta.quantity).and_then(|quantity| i32::try_from(quantity).ok()),
+            interval,
+            last_height,
+            deposited_amount,
+            in_amount,
+            out_amount: amount(&meta.outCoin),
+            fill_percent: in_amount
+                .zip(deposited_amount)
+                .and_then(|(in_amount, deposited)| fill_percent(in_amount, deposited)),
+            duration_secs: blocks.map(|blocks| blocks * THORCHAIN_BLOCK_SECS),
+        })
+    }
+
+    // Synth, trade or secured when any leg holds such an asset, native otherwise
+    pub fn asset_kind(&self) -> AssetKind {
+        self.in_data
+            .iter()
+            .chain(&self.out_data)
+            .flat_map(|data| &data.coins)
+            .filter_map(|coin| Asset::parse(&coin.asset).ok())
+            .map(|asset| asset.kind)
+            .find(|kind| *kind != AssetKind::Native)
+            .unwrap_or(AssetKind::Native)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamingSwapDetails {
+    // Sub-swaps executed and planned
+    pub count: Option<i32>,
+    pub quantity: Option<i32>,
+    // Blocks between sub-swaps
+    pub interval: Option<i32>,
+    pub last_height: Option<i64>,
+    pub deposited_amount: Option<Decimal>,
+    // Part of the deposit swapped so far and what it bought
+    pub in_amount: Option<Decimal>,
+    pub out_amount: Option<Decimal>,
+    pub fill_percent: Option<Decimal>,
+    pub duration_secs: Option<i64>,
+}
+
+// Share of the deposit that was swapped, as a percentage with two decimals
+pub fn fill_percent(in_amount: Decimal, deposited_amount: Decimal) -> Option<Decimal> {
+    if deposited_amount <= Decimal::ZERO {
+        return None;
+    }
+    Some(
+        (in_amount * Decimal::ONE_HUNDRED / deposited_amount)
+            .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapOutcome {
+    Success,
+    Refund,
+    PartialRefund,
+}
+
+impl SwapOutcome {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "success" => Some(SwapOutcome::Success),
+            "refund" => Some(SwapOutcome::Refund),
+            "partial_refund" => Some(SwapOutcome::PartialRefund),
+            _ => None,
+        }
+    }
+
+    pub fn of(action: &SwapTransaction) -> Self {
+        let (delivered, refunds) = action.split_out_legs();
+        match (delivered.is_empty(), refunds.is_empty()) {
+            (_, true) => SwapOutcome::Success,
+            (true, false) => SwapOutcome::Refund,
+            (false, false) => SwapOutcome::PartialRefund,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SwapOutcome::Success => "success",
+            SwapOutcome::Refund => "refund",
+            SwapOutcome::PartialRefund => "partial_refund",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PricingStatus {
+    // Every leg has a USD value and a value in every configured quote currency
+    Priced,
+    // Some leg is missing its USD or a quote currency value, the repricing job retries it
+    Unpriced,
+    // Repricing gave up after its maximum number of attempts
+    Failed,
+}
+
+impl PricingStatus {
+    pub fn of(swap: &SwapTransactionFromatted, currencies: &[QuoteCurrency]) -> Self {
+        if swap.missing_usd_values() == 0 && swap.missing_quote_values(currencies) == 0 {
+            PricingStatus::Priced
+        } else {
+            PricingStatus::Unpriced
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PricingStatus::Priced => "priced",
+            PricingStatus::Unpriced => "unpriced",
+            PricingStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapStatus {
+    Pending,
+    Success,
+    Refund,
+    // Still pending when reconciliation gave up on it
+    Abandoned,
+}
+
+impl SwapStatus {
+    pub fn of(action: &SwapTransaction) -> Self {
+        if action.status == "pending" {
+            SwapStatus::Pending
+        } else if action.action_type == "refund" {
+            SwapStatus::Refund
+        } else {
+            SwapStatus::Success
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SwapStatus::Pending => "pending",
+            SwapStatus::Success => "success",
+            SwapStatus::Refund => "refund",
+            SwapStatus::Abandoned => "abandoned",
+        }
+    }
+
+    // Whether a stored status is final, pending and abandoned rows are still replaced once
+    // the swap settles
+    pub fn is_settled(status: &str) -> bool {
+        status != SwapStatus::Pending.as_str() && status != SwapStatus::Abandoned.as_str()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValuationLeg {
+    In,
+    Out1,
+    Out2,
+    Refund,
+}
+
+impl ValuationLeg {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValuationLeg::In => "in",
+            ValuationLeg::Out1 => "out_1",
+            ValuationLeg::Out2 => "out_2",
+            ValuationLeg::Refund => "refund",
+        }
+    }
+}
+
+// Where one USD value of a swap row came from
+#[derive(Debug, Clone, FromRow, Serialize, PartialEq)]
+pub struct SwapValuation {
+    #[serde(skip)]
+    pub tx_id: String,
+    #[serde(skip)]
+    pub leg_key: String,
+    pub leg: String,
+    pub asset: String,
+    pub provider: String,
+    pub coin_id: Option<String>,
+    pub unit_price_usd: Decimal,
+    pub amount_usd: Decimal,
+    // When the price was observed, compare with swap_timestamp for how stale it was
+    pub price_timestamp: i64,
+    pub swap_timestamp: i64,
+    pub confidence: String,
+}
+
+// A leg's value in a quote currency other than USD
+#[derive(Debug, Clone, FromRow, Serialize, PartialEq)]
+pub struct SwapQuoteValue {
+    #[serde(skip)]
+    pub tx_id: String,
+    #[serde(skip)]
+    pub leg_key: String,
+    pub leg: String,
+    pub currency: String,
+    pub amount: Decimal,
+    // USD per unit of the currency at swap time
+    pub usd_rate: Decimal,
+}
+
+// One out leg paid back by a refund, a refund can be split over several outbound txs
+#[derive(Debug, Clone, FromRow, Serialize, PartialEq)]
+pub struct SwapRefundLeg {
+    #[serde(skip)]
+    pub tx_id: String,
+    #[serde(skip)]
+    pub leg_key: String,
+    pub leg_index: i32,
+    pub asset: String,
+    pub amount: Decimal,
+    pub address: String,
+}
+
+// The first refund leg and the total refunded in its asset, what the refund columns hold and
+// what is valued. Legs in any other asset are only kept in refund_legs.
+pub fn refund_total(legs: &[SwapRefundLeg]) -> Option<(&SwapRefundLeg, Decimal)> {
+    let first = legs.first()?;
+    let total = legs
+        .iter()
+        .filter(|leg| leg.asset == first.asset)
+        .map(|leg| leg.amount)
+        .sum();
+    Some((first, total))
+}
+
+// A stored swap leg waiting for its USD values
+#[derive(Debug, Clone, FromRow)]
+pub struct UnpricedSwap {
+    #[sqlx(flatten)]
+    pub swap: SwapTransactionFromatted,
+    pub pricing_attempts: i32,
+}
+
+// A status observed for an inbound tx, recorded in the swap's status history
+#[derive(Debug, Clone)]
+pub struct SwapStatusUpdate {
+    pub tx_id: String,
+    pub status: SwapStatus,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct SwapStatusChange {
+    #[serde(skip)]
+    pub tx_id: String,
+    pub status: String,
+    pub observed_at: i64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -50,19 +461,183 @@ pub struct ActionsFetchResponse {
 #[derive(Debug, Clone, FromRow, Serialize)]
 pub struct SwapTransactionFromatted {
     pub timestamp: i64,
-    pub date: String,
+    // UTC day of the swap
+    pub date: NaiveDate,
     pub time: String,
     pub in_asset: String,
-    pub in_amount: f64,
-    pub in_amount_usd: f64,
-    pub out_asset_1: String,
-    pub out_amount_1: f64,
-    pub out_amount_1_usd: f64,
+    pub in_amount: Decimal,
+    pub in_amount_usd: Option<Decimal>,
+    pub out_asset_1: Option<String>,
+    pub out_amount_1: Option<Decimal>,
+    pub out_amount_1_usd: Option<Decimal>,
     pub in_address: String,
-    pub out_address_1: String,
+    pub out_address_1: Option<String>,
     pub tx_id: String,
     pub out_asset_2: Option<String>,
-    pub out_amount_2: Option<f64>,
-    pub out_amount_2_usd: Option<f64>,
+    pub out_amount_2: Option<Decimal>,
+    pub out_amount_2_usd: Option<Decimal>,
     pub out_address_2: Option<String>,
+    pub status: String,
+    pub outcome: String,
+    pub refund_asset: Option<String>,
+    pub refund_amount: Option<Decimal>,
+    pub refund_amount_usd: Option<Decimal>,
+    pub refund_address: Option<String>,
+    pub refund_reason: Option<String>,
+    pub pricing_status: String,
+    // native | synth | trade | secured
+    pub asset_kind: String,
+    // The asset columns keep the CHAIN.TICKER form every row has, these hold the full asset
+    // with its kind and contract. None on rows stored before they were added.
+    pub in_asset_canonical: Option<String>,
+    pub out_asset_1_canonical: Option<String>,
+    pub out_asset_2_canonical: Option<String>,
+    pub refund_asset_canonical: Option<String>,
+    // Streaming swap progress, all None for single block swaps
+    pub streaming_count: Option<i32>,
+    pub streaming_quantity: Option<i32>,
+    pub streaming_interval: Option<i32>,
+    pub streaming_last_height: Option<i64>,
+    pub streaming_deposited_amount: Option<Decimal>,
+    pub streaming_in_amount: Option<Decimal>,
+    pub streaming_out_amount: Option<Decimal>,
+    pub streaming_fill_percent: Option<Decimal>,
+    pub streaming_duration_secs: Option<i64>,
+    #[sqlx(skip)]
+    pub status_history: Vec<SwapStatusChange>,
+    #[sqlx(skip)]
+    pub valuations: Vec<SwapValuation>,
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub refund_legs: Vec<SwapRefundLeg>,
+    // Loaded for the currency a request asks for, or all of them for repricing
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quote_values: Vec<SwapQuoteValue>,
+}
+
+impl SwapTransactionFromatted {
+    // Distinguishes the separate swap actions a single inbound tx can produce, e.g. the
+    // affiliate fee swap that shares its txID with the user's swap
+    pub fn leg_key(&self) -> String {
+        format!(
+            "{}|{}|{}",
+            short_asset_name(&self.in_asset),
+            self.out_asset_1
+                .as_deref()
+                .map(short_asset_name)
+                .unwrap_or_default(),
+            self.out_address_1.as_deref().unwrap_or_default()
+        )
+    }
+
+    // Number of populated optional values, used to prefer the richer of two copies of a swap
+    pub fn completeness(&self) -> usize {
+        [
+            self.in_amount_usd.is_some_and(|usd| !usd.is_zero()),
+            self.out_amount_1_usd.is_some_and(|usd| !usd.is_zero()),
+            self.out_asset_2.is_some(),
+            self.out_amount_2.is_some(),
+            self.out_amount_2_usd.is_some_and(|usd| !usd.is_zero()),
+            self.out_address_2.is_some(),
+            self.refund_amount_usd.is_some_and(|usd| !usd.is_zero()),
+        ]
+        .iter()
+        .filter(|present| **present)
+        .count()
+    }
+
+    pub fn set_streaming_details(&mut self, details: Option<&StreamingSwapDetails>) {
+        self.streaming_count = details.and_then(|details| details.count);
+        self.streaming_quantity = details.and_then(|details| details.quantity);
+        self.streaming_interval = details.and_then(|details| details.interval);
+        self.streaming_last_height = details.and_then(|details| details.last_height);
+        self.streaming_deposited_amount = details.and_then(|details| details.deposited_amount);
+        self.streaming_in_amount = details.and_then(|details| details.in_amount);
+        self.streaming_out_amount = details.and_then(|details| details.out_amount);
+        self.streaming_fill_percent = details.and_then(|details| details.fill_percent);
+        self.streaming_duration_secs = details.and_then(|details| details.duration_secs);
+    }
+
+    // Takes the USD values this copy is missing, with their provenance, from another valuation
+    // of the same leg. Quote currency values it lacks are converted from its own USD values at
+    // the other copy's rates.
+    pub fn fill_usd_values(&mut self, other: &SwapTransactionFromatted) {
+        let filled: Vec<&str> = [
+            (self.in_amount_usd, ValuationLeg::In),
+            (self.out_amount_1_usd, ValuationLeg::Out1),
+            (self.out_amount_2_usd, ValuationLeg::Out2),
+            (self.refund_amount_usd, ValuationLeg::Refund),
+        ]
+        .iter()
+        .filter(|(usd, _)| usd.is_none())
+        .map(|(_, leg)| leg.as_str())
+        .collect();
+        self.valuations.extend(
+            other
+                .valuations
+                .iter()
+                .filter(|valuation| filled.contains(&valuation.leg.as_str()))
+                .cloned(),
+        );
+        let leg_key = self.leg_key();
+        for value in &other.quote_values {
+            let present = self
+                .quote_values
+                .iter()
+                .any(|existing| existing.leg == value.leg && existing.currency == value.currency);
+            let valuation = self
+                .valuations
+                .iter()
+                .find(|valuation| valuation.leg == value.leg);
+            let (false, Some(valuation), Some(currency)) =
+                (present, valuation, QuoteCurrency::parse(&value.currency))
+            else {
+                continue;
+            };
+            let Some(amount) = currency.convert_usd(valuation.amount_usd, value.usd_rate) else {
+                continue;
+            };
+            self.quote_values.push(SwapQuoteValue {
+                tx_id: self.tx_id.clone(),
+                leg_key: leg_key.clone(),
+                leg: value.leg.clone(),
+                currency: value.currency.clone(),
+                amount,
+                usd_rate: value.usd_rate,
+            });
+        }
+
+        self.in_amount_usd = self.in_amount_usd.or(other.in_amount_usd);
+        self.out_amount_1_usd = self.out_amount_1_usd.or(other.out_amount_1_usd);
+        self.out_amount_2_usd = self.out_amount_2_usd.or(other.out_amount_2_usd);
+        self.refund_amount_usd = self.refund_amount_usd.or(other.refund_amount_usd);
+    }
+
+    // Valued legs without a value in one of the currencies
+    pub fn missing_quote_values(&self, currencies: &[QuoteCurrency]) -> usize {
+        self.valuations
+            .iter()
+            .flat_map(|valuation| currencies.iter().map(move |currency| (valuation, currency)))
+            .filter(|(valuation, currency)| {
+                !self
+                    .quote_values
+                    .iter()
+                    .any(|value| value.leg == valuation.leg && value.currency == currency.as_str())
+            })
+            .count()
+    }
+
+    // Legs with an amount but no USD value
+    pub fn missing_usd_values(&self) -> usize {
+        [
+            self.in_amount_usd.is_none(),
+            self.out_amount_1.is_some() && self.out_amount_1_usd.is_none(),
+            self.out_amount_2.is_some() && self.out_amount_2_usd.is_none(),
+            self.refund_amount.is_some() && self.refund_amount_usd.is_none(),
+        ]
+        .iter()
+        .filter(|missing| **missing)
+        .count()
+    }
 }
@@ -0,0 +1,22 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestionJob {
+    Backfill,
+    Tail,
+}
+
+impl IngestionJob {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IngestionJob::Backfill => "backfill",
+            IngestionJob::Tail => "tail",
+        }
+    }
+}
+
+// Cursor to persist once the page it follows has been written
+#[derive(Debug, Clone)]
+pub struct Checkpoint<'a> {
+    pub source: &'a str,
+    pub job: IngestionJob,
+    pub token: &'a str,
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct CoinMapping {
+    pub asset: String,
+    pub coin_id: String,
+    // curated | search | override
+    pub source: String,
+    pub discovered_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CoinMappingUpdate {
+    pub asset: String,
+    pub coin_id: String,
+}
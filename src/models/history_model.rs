@@ -0,0 +1,27 @@
+#![allow(non_snake_case)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DepthHistoryInterval {
+    pub startTime: String,
+    pub endTime: String,
+    pub assetDepth: String,
+    pub runeDepth: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DepthHistoryResponse {
+    pub intervals: Vec<DepthHistoryInterval>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunePriceInterval {
+    pub startTime: String,
+    pub endTime: String,
+    pub runePriceUSD: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunePriceHistoryResponse {
+    pub intervals: Vec<RunePriceInterval>,
+}
@@ -1,9 +1,16 @@
 use serde::{Deserialize, Serialize};
 pub mod actions_model;
+pub mod checkpoint_model;
+pub mod coin_mapping_model;
+pub mod history_model;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CurrentPrice {
     pub usd: f64,
+    #[serde(default)]
+    pub eur: Option<f64>,
+    #[serde(default)]
+    pub gbp: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -31,3 +38,9 @@ pub struct CoinSearchData {
 pub struct CoinSearchResponse {
     pub coins: Vec<CoinSearchData>,
 }
+
+// Each price is a [unix milliseconds, price] pair
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MarketChartResponse {
+    pub prices: Vec<(f64, f64)>,
+}
@@ -0,0 +1,83 @@
+use actix_web::{
+    get, put,
+    web::{self, ServiceConfig},
+    HttpRequest, HttpResponse, Responder,
+};
+use dotenv::dotenv;
+use std::env;
+
+use crate::{
+    db::MySQL,
+    models::coin_mapping_model::CoinMappingUpdate,
+    utils::{
+        coin_registry::{CoinIdSource, CoinRegistry},
+        coingecko::CoinGecko,
+    },
+};
+
+// Edits need the x-admin-key header to match ADMIN_API_KEY, and are refused when it isn't set
+fn is_admin(req: &HttpRequest) -> bool {
+    dotenv().ok();
+    let Ok(admin_key) = env::var("ADMIN_API_KEY") else {
+        return false;
+    };
+    !admin_key.is_empty()
+        && req
+            .headers()
+            .get("x-admin-key")
+            .and_then(|value| value.to_str().ok())
+            == Some(admin_key.as_str())
+}
+
+#[get("/coin-mappings")]
+pub async fn list_coin_mappings(mysql: web::Data<MySQL>) -> impl Responder {
+    match mysql.fetch_coin_mappings().await {
+        Ok(mappings) => HttpResponse::Ok().json(mappings),
+        Err(err) => {
+            println!("{:?}", err);
+            HttpResponse::BadRequest().json("Error Fetching Data")
+        }
+    }
+}
+
+#[put("/coin-mappings")]
+pub async fn update_coin_mapping(
+    req: HttpRequest,
+    mysql: web::Data<MySQL>,
+    coingecko: Option<web::Data<CoinGecko>>,
+    update: web::Json<CoinMappingUpdate>,
+) -> impl Responder {
+    if !is_admin(&req) {
+        return HttpResponse::Forbidden().json("Admin key required");
+    }
+    let update = update.into_inner();
+    if update.asset.trim().is_empty() || update.coin_id.trim().is_empty() {
+        return HttpResponse::BadRequest().json("asset and coin_id are required");
+    }
+
+    // Saved under the spelling lookups normalize to, so the override applies after a restart
+    let asset = CoinRegistry::normalize(&update.asset);
+    if let Err(err) = mysql
+        .save_coin_mapping(
+            &asset,
+            update.coin_id.trim(),
+            CoinIdSource::Override.as_str(),
+        )
+        .await
+    {
+        println!("{:?}", err);
+        return HttpResponse::BadRequest().json("Error Saving Data");
+    }
+    // Without a CoinGecko client the mapping is only picked up once one is configured
+    if let Some(coingecko) = coingecko {
+        coingecko.set_override(&asset, update.coin_id.trim());
+    }
+
+    HttpResponse::Ok().json(update.coin_id.trim())
+}
+
+pub fn init(config: &mut ServiceConfig) {
+    config
+        .service(list_coin_mappings)
+        .service(update_coin_mapping);
+}
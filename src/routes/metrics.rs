@@ -0,0 +1,13 @@
+use actix_web::{get, web::ServiceConfig, HttpResponse, Responder};
+
+use crate::utils::coingecko::COINGECKO_RATE_LIMITER;
+
+// Remaining CoinGecko budget and how much the limiter has throttled so far
+#[get("/metrics/coingecko")]
+pub async fn coingecko_metrics() -> impl Responder {
+    HttpResponse::Ok().json(COINGECKO_RATE_LIMITER.metrics())
+}
+
+pub fn init(config: &mut ServiceConfig) {
+    config.service(coingecko_metrics);
+}
@@ -1 +1,3 @@
+pub mod coin_mappings;
+pub mod metrics;
 pub mod swap_history;
\ No newline at end of file
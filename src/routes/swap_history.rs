@@ -5,8 +5,13 @@ use actix_web::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{db::MySQL, utils::parse_u64};
+use crate::{
+    db::MySQL,
+    models::actions_model::SwapOutcome,
+    utils::{asset::AssetKind, currency::QuoteCurrency, parse_u64},
+};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum OrderType {
     ASC,
@@ -20,6 +25,21 @@ pub struct RequestBody {
     order: String,
     search: Option<String>,
     date: Option<String>,
+    // success | refund | partial_refund
+    outcome: Option<String>,
+    // eur | gbp | btc | rune, adds each leg's value in that currency
+    currency: Option<String>,
+    // native | synth | trade | secured
+    asset_kind: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SwapFilters {
+    pub search: Option<String>,
+    pub date: Option<String>,
+    pub outcome: Option<SwapOutcome>,
+    pub currency: Option<QuoteCurrency>,
+    pub asset_kind: Option<AssetKind>,
 }
 #[post("/swaps")]
 pub async fn swap_history(
@@ -27,12 +47,26 @@ pub async fn swap_history(
     options: web::Json<RequestBody>,
 ) -> impl Responder {
     let options = options.into_inner();
-    let order;
-    if options.order == "ASC" {
-        order = OrderType::ASC;
+    let currency = match options.currency.as_deref().map(QuoteCurrency::parse) {
+        None | Some(Some(QuoteCurrency::Usd)) => None,
+        Some(Some(currency)) => Some(currency),
+        Some(None) => return HttpResponse::BadRequest().json("Unsupported currency"),
+    };
+    let asset_kind = match options.asset_kind.as_deref().map(AssetKind::parse) {
+        None => None,
+        Some(Some(kind)) => Some(kind),
+        Some(None) => return HttpResponse::BadRequest().json("Unsupported asset kind"),
+    };
+    let outcome = match options.outcome.as_deref().map(SwapOutcome::parse) {
+        None => None,
+        Some(Some(outcome)) => Some(outcome),
+        Some(None) => return HttpResponse::BadRequest().json("Unsupported outcome"),
+    };
+    let order = if options.order == "ASC" {
+        OrderType::ASC
     } else {
-        order = OrderType::DESC;
-    }
+        OrderType::DESC
+    };
     let page = parse_u64(&options.page).unwrap();
     let limit = parse_u64(&options.limit).unwrap();
     let offset: u64 = (page - 1) * limit;
@@ -42,8 +76,13 @@ pub async fn swap_history(
             limit,
             options.sort_by,
             offset,
-            options.search,
-            options.date,
+            SwapFilters {
+                search: options.search,
+                date: options.date,
+                outcome,
+                currency,
+                asset_kind,
+            },
         )
         .await;
     match records {
@@ -1,23 +1,83 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
+    use crate::db::{plan_page, ConflictMode, UpsertOutcome, UpsertSummary};
+    use crate::models::actions_model::{
+        fill_percent, refund_total, ActionsFetchResponse, PricingStatus, SwapOutcome,
+        SwapQuoteValue, SwapRefundLeg, SwapStatus, SwapTransactionFromatted, SwapValuation,
+        ValuationLeg,
+    };
+    use crate::utils::asset::{short_asset_name, underlying_asset, Asset, AssetError, AssetKind};
+    use crate::utils::coin_registry::{CoinIdSource, CoinRegistry, Confidence};
+    use crate::utils::currency::QuoteCurrency;
+    use crate::utils::midgard::{
+        EndpointHealth, HistoryInterval, MidGard, MidGardConfig, MidGardError,
+    };
+    use crate::utils::price_cache::PriceCache;
+    use crate::utils::price_oracle::{
+        interpolate_price, pool_price_usd, ChartResolution, CompositeOracle, MidgardMetadataOracle,
+        OracleError, PriceOracle, PriceProvider, ProviderKind, StaticPriceOracle,
+    };
+    use crate::utils::rate_limiter::{with_priority, ApiPlan, Priority, RateLimiter};
+    use crate::utils::retry::{parse_retry_after, FailureClass, FetchError, RetryPolicy};
+    use crate::utils::single_flight::SingleFlight;
+    use crate::utils::transaction_handler::{
+        quote_values, relative_deviation, usd_amount, TransactionHandler, ValuationConfig,
+        ValuationMode,
+    };
     use crate::utils::{
-        asset_name_from_pool, calculate_transaction_amount, coin_name_from_pool,
-        convert_nano_to_sec, convert_to_standard_unit, format_date_for_sql, parse_f64, parse_u64,
-        read_next_page_token_from_file, write_next_page_token_to_file,
+        calculate_transaction_amount, convert_nano_to_sec, convert_to_standard_unit,
+        format_date_for_sql, format_epoch_timestamp, parse_amount, parse_f64, parse_u64,
+        price_to_decimal, read_next_page_token_from_file,
     };
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    use reqwest::StatusCode;
+    use std::time::Duration;
+
+    fn dec(value: &str) -> Decimal {
+        parse_amount(value).unwrap()
+    }
+
+    #[test]
+    fn test_usd_amount_is_exact() {
+        // 0.1 + 0.2 style float error would show up in the cents
+        assert_eq!(usd_amount(dec("30000000"), dec("0.1")), dec("0.03"));
+        assert_eq!(
+            usd_amount(dec("250000000000000"), dec("64321.987654321")),
+            dec("160804969135.80")
+        );
+        // Half a cent rounds away from zero, once
+        assert_eq!(usd_amount(dec("100000000"), dec("0.125")), dec("0.13"));
 
-    use std::fs;
+        assert_eq!(price_to_decimal(2027.22), Some(dec("2027.22")));
+        assert_eq!(price_to_decimal(0.0), None);
+        assert_eq!(price_to_decimal(f64::NAN), None);
+    }
 
     #[test]
     fn test_convert_to_standard_unit() {
-        assert_eq!(convert_to_standard_unit(1000000.0, 6), 1.0);
-        assert_eq!(convert_to_standard_unit(123456789.0, 6), 123.456789);
+        assert_eq!(convert_to_standard_unit(dec("1000000"), 6), dec("1"));
+        assert_eq!(
+            convert_to_standard_unit(dec("123456789"), 6),
+            dec("123.456789")
+        );
+        // Whale sized amounts keep every base unit
+        assert_eq!(
+            convert_to_standard_unit(dec("123456789012345678901"), 8),
+            dec("1234567890123.45678901")
+        );
     }
 
     #[test]
     fn test_calculate_transaction_amount() {
-        assert_eq!(calculate_transaction_amount(10.0, 2.5), 25.0);
-        assert_eq!(calculate_transaction_amount(5.0, 3.0), 15.0);
+        assert_eq!(
+            calculate_transaction_amount(dec("10"), dec("2.5")),
+            dec("25")
+        );
+        assert_eq!(calculate_transaction_amount(dec("5"), dec("3")), dec("15"));
     }
 
     #[test]
@@ -38,44 +98,1301 @@ mod tests {
         assert!(parse_u64("xyz").is_err());
     }
 
+    fn pool_assets() -> Vec<String> {
+        let pools: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("fixtures/pools.json")).unwrap();
+        pools
+            .iter()
+            .map(|pool| pool["asset"].as_str().unwrap().to_string())
+            .collect()
+    }
+
     #[test]
-    fn test_coin_name_from_pool() {
-        assert_eq!(coin_name_from_pool("pool.btc~usd"), Some("btc".to_string()));
-        assert_eq!(coin_name_from_pool("pool-eth-usd"), Some("eth".to_string()));
-        assert_eq!(coin_name_from_pool("poolname"), None);
+    fn test_asset_round_trips_every_pool() {
+        let pools = pool_assets();
+        assert!(!pools.is_empty());
+        for pool in pools {
+            let asset = Asset::parse(&pool).unwrap();
+            assert_eq!(asset.kind, AssetKind::Native);
+            assert_eq!(asset.to_string(), pool);
+            assert_eq!(Asset::parse(&asset.to_string()).unwrap(), asset);
+            assert_eq!(
+                asset.short_name(),
+                format!("{}.{}", asset.chain, asset.ticker)
+            );
+
+            // Synth, trade and secured forms keep the same chain and symbol
+            for kind in [AssetKind::Synth, AssetKind::Trade, AssetKind::Secured] {
+                let variant = format!("{}{}{}", asset.chain, kind.separator(), asset.symbol);
+                let parsed = Asset::parse(&variant).unwrap();
+                assert_eq!(parsed.kind, kind);
+                assert_eq!(parsed.chain, asset.chain);
+                assert_eq!(parsed.ticker, asset.ticker);
+                assert_eq!(parsed.contract, asset.contract);
+                assert_eq!(parsed.to_string(), variant);
+                assert_eq!(parsed.short_name(), asset.short_name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_asset_parts() {
+        let usdt: Asset = "eth.usdt-0xdac17f958d2ee523a2206206994597c13d831ec7"
+            .parse()
+            .unwrap();
+        assert_eq!(usdt.chain, "ETH");
+        assert_eq!(
+            usdt.symbol,
+            "USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7"
+        );
+        assert_eq!(usdt.ticker, "USDT");
+        assert_eq!(
+            usdt.contract.as_deref(),
+            Some("0XDAC17F958D2EE523A2206206994597C13D831EC7")
+        );
+        assert_eq!(
+            usdt.to_string(),
+            "ETH.USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7"
+        );
+        assert_eq!(usdt.short_name(), "ETH.USDT");
+
+        let secured_usdt =
+            Asset::parse("ETH-USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7").unwrap();
+        assert_eq!(secured_usdt.kind, AssetKind::Secured);
+        assert_eq!(secured_usdt.contract, usdt.contract);
+
+        assert!(Asset::parse(" THOR.RUNE ").unwrap().is_rune());
+        assert!(!Asset::parse("THOR/RUNE-0X1").unwrap().is_rune());
+        assert!(
+            !Asset::parse("ETH.RUNE-0X3155BA85D5F96B2D030A4966AF206230E46849CB")
+                .unwrap()
+                .is_rune()
+        );
+    }
+
+    #[test]
+    fn test_asset_rejects_malformed() {
+        assert!(matches!(
+            Asset::parse("BTCBTC"),
+            Err(AssetError::MissingSeparator(_))
+        ));
+        assert!(matches!(
+            Asset::parse(""),
+            Err(AssetError::MissingSeparator(_))
+        ));
+        assert!(matches!(
+            Asset::parse(".BTC"),
+            Err(AssetError::InvalidChain(_))
+        ));
+        assert!(matches!(
+            Asset::parse("BTC."),
+            Err(AssetError::InvalidTicker(_))
+        ));
+        assert!(matches!(
+            Asset::parse("BTC.B TC"),
+            Err(AssetError::InvalidTicker(_))
+        ));
+        assert!(matches!(
+            Asset::parse("ETH.USDT-"),
+            Err(AssetError::InvalidContract(_))
+        ));
+        assert!(matches!(
+            Asset::parse("ETH.USDT-0X1-0X2"),
+            Err(AssetError::InvalidContract(_))
+        ));
+        assert!(matches!(
+            Asset::parse("ETH.USDT.0X1"),
+            Err(AssetError::InvalidTicker(_))
+        ));
+        assert!(matches!(
+            Asset::parse("ETH/BTC~BTC"),
+            Err(AssetError::InvalidTicker(_))
+        ));
     }
 
     #[test]
-    fn test_asset_name_from_pool() {
-        assert_eq!(asset_name_from_pool("btc.eth"), Some("btc.eth".to_string()));
+    fn test_leg_key_uses_short_asset_names() {
+        let short = sample_swap();
+        let full = SwapTransactionFromatted {
+            in_asset: "BTC.BTC".to_string(),
+            out_asset_1: Some("ETH.USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7".to_string()),
+            ..sample_swap()
+        };
+        let legacy = SwapTransactionFromatted {
+            out_asset_1: Some("ETH.USDT".to_string()),
+            ..sample_swap()
+        };
+        assert_eq!(full.leg_key(), legacy.leg_key());
+        assert_ne!(full.leg_key(), short.leg_key());
+    }
+
+    #[test]
+    fn test_short_asset_name() {
         assert_eq!(
-            asset_name_from_pool("usd-btc~eth"),
-            Some("usd.btc".to_string())
+            short_asset_name("ETH.USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7"),
+            "ETH.USDT"
         );
-        assert_eq!(asset_name_from_pool("name"), None);
+        assert_eq!(short_asset_name("btc/btc"), "BTC.BTC");
+        assert_eq!(short_asset_name("ETH-ETH"), "ETH.ETH");
+        assert_eq!(short_asset_name("not an asset"), "not an asset");
     }
 
     #[test]
     fn test_format_date_for_sql() {
         assert_eq!(format_date_for_sql("14-08-2023").unwrap(), "2023-08-14");
         assert!(format_date_for_sql("invalid-date").is_err());
+
+        // Swaps carry their day as a date, stored and returned as YYYY-MM-DD
+        let (date, time) = format_epoch_timestamp("1700000000000000000").unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2023, 11, 14).unwrap());
+        assert_eq!(date.to_string(), "2023-11-14");
+        assert_eq!(time, "10:13pm");
+    }
+
+    #[test]
+    fn test_midgard_actions_path_uses_config() {
+        let midgard = MidGard::new(MidGardConfig {
+            base_urls: vec!["http://localhost:8080/v2".to_string()],
+            action_type: "swap".to_string(),
+            asset: "".to_string(),
+            timeout: Duration::from_secs(1),
+            ..MidGardConfig::default()
+        })
+        .unwrap();
+
+        assert_eq!(midgard.actions_path(None), "/actions?type=swap");
+        assert_eq!(
+            midgard.actions_path(Some(("nextPageToken", "123"))),
+            "/actions?type=swap&nextPageToken=123"
+        );
+    }
+
+    #[test]
+    fn test_midgard_actions_path_defaults() {
+        let midgard = MidGard::new(MidGardConfig::default()).unwrap();
+
+        assert_eq!(
+            midgard.actions_path(Some(("nextPageToken", ""))),
+            "/actions?type=swap,refund"
+        );
+        assert_eq!(
+            midgard.actions_path(Some(("fromTimestamp", "1700000000"))),
+            "/actions?type=swap,refund&fromTimestamp=1700000000"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_health_score() {
+        let mut fast_failing = EndpointHealth::default();
+        fast_failing.record_success(Duration::from_millis(50));
+        fast_failing.record_failure(Duration::from_millis(50));
+
+        let mut slow_healthy = EndpointHealth::default();
+        slow_healthy.record_success(Duration::from_millis(800));
+        slow_healthy.record_success(Duration::from_millis(800));
+
+        assert!(slow_healthy.score() < fast_failing.score());
+        assert_eq!(fast_failing.consecutive_failures, 1);
+
+        fast_failing.record_success(Duration::from_millis(50));
+        assert_eq!(fast_failing.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_endpoint_health_recovers_over_time() {
+        let mut failing = EndpointHealth::default();
+        for _ in 0..5 {
+            failing.record_failure(Duration::from_millis(50));
+        }
+        let mut slow_healthy = EndpointHealth::default();
+        slow_healthy.record_success(Duration::from_millis(800));
+
+        let failed_at = failing.last_failure.unwrap();
+        assert!(failing.score_at(failed_at) > slow_healthy.score_at(failed_at));
+
+        // Without further failures the endpoint moves back ahead and is tried again
+        let later = failed_at + Duration::from_secs(600);
+        assert!(failing.score_at(later) < slow_healthy.score_at(later));
+        assert!(failing.score_at(later) < failing.score_at(failed_at));
+    }
+
+    #[test]
+    fn test_midgard_requires_an_endpoint() {
+        let midgard = MidGard::new(MidGardConfig {
+            base_urls: Vec::new(),
+            ..MidGardConfig::default()
+        });
+        assert!(matches!(midgard, Err(MidGardError::NoEndpoints)));
+    }
+
+    #[test]
+    fn test_midgard_ranks_endpoints_by_health() {
+        let midgard = MidGard::new(MidGardConfig {
+            base_urls: vec![
+                "http://primary".to_string(),
+                "http://secondary".to_string(),
+                "http://tertiary".to_string(),
+            ],
+            ..MidGardConfig::default()
+        })
+        .unwrap();
+
+        // Untested endpoints keep their configured order
+        assert_eq!(midgard.ranked_endpoints(), vec![0, 1, 2]);
+
+        midgard.record_result(0, Duration::from_millis(100), false);
+        midgard.record_result(1, Duration::from_millis(900), true);
+        midgard.record_result(2, Duration::from_millis(200), true);
+        assert_eq!(midgard.ranked_endpoints(), vec![2, 1, 0]);
+
+        // Health is shared between clones of the client
+        let clone = midgard.clone();
+        clone.record_result(2, Duration::from_millis(100), false);
+        assert_eq!(midgard.ranked_endpoints(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_retry_policy_backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(64), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_retry_policy_jitter_and_retry_after() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+
+        for _ in 0..50 {
+            let delay = policy.delay_for(3, None);
+            assert!(delay <= policy.backoff(3));
+            assert!(delay >= policy.backoff(3) / 2);
+        }
+        assert_eq!(
+            policy.delay_for(1, Some(Duration::from_secs(7))),
+            Duration::from_secs(7)
+        );
+        // A Retry-After longer than max_delay is capped like the backoff
+        assert_eq!(
+            policy.delay_for(1, Some(policy.max_delay * 10)),
+            policy.max_delay
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_fetch_error_classification() {
+        let retryable = |code: u16| {
+            FetchError::from_status(StatusCode::from_u16(code).unwrap(), None).is_retryable()
+        };
+
+        assert!(retryable(429));
+        assert!(retryable(408));
+        assert!(retryable(500));
+        assert!(retryable(503));
+        assert!(!retryable(400));
+        assert!(!retryable(404));
+        assert_eq!(
+            FetchError::from_status(StatusCode::TOO_MANY_REQUESTS, None).class,
+            FailureClass::RateLimited
+        );
     }
 
-    const TOKEN_FILE_PATH: &str = "next_page_token.txt";
+    #[tokio::test]
+    async fn test_retry_policy_reports_final_failure_class() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            jitter: 0.0,
+        };
+
+        let mut calls = 0;
+        let result: Result<(), _> = policy
+            .run("test", || {
+                calls += 1;
+                async { Err(FetchError::from_status(StatusCode::BAD_GATEWAY, None)) }
+            })
+            .await;
+        let err = result.unwrap_err();
+        assert_eq!(err.attempts, 3);
+        assert_eq!(err.class, FailureClass::ServerError);
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result: Result<(), _> = policy
+            .run("test", || {
+                calls += 1;
+                async { Err(FetchError::from_status(StatusCode::NOT_FOUND, None)) }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().class, FailureClass::ClientError);
+        assert_eq!(calls, 1);
+    }
 
     #[test]
     fn test_read_next_page_token_from_file() {
-        let token = read_next_page_token_from_file().unwrap_or("default".to_string());
-        assert!(token.is_empty() || token == "170981189000000012" || token == "default");
+        // Either the legacy cursor on disk or the default starting token
+        let token = read_next_page_token_from_file().unwrap();
+        assert!(parse_u64(&token).is_ok());
+    }
+
+    fn sample_swap() -> SwapTransactionFromatted {
+        SwapTransactionFromatted {
+            timestamp: 1700000000,
+            date: NaiveDate::from_ymd_opt(2023, 11, 14).unwrap(),
+            time: "10:13pm".to_string(),
+            in_asset: "BTC.BTC".to_string(),
+            in_amount: dec("1000000"),
+            in_amount_usd: Some(dec("365.12")),
+            out_asset_1: Some("ETH.ETH".to_string()),
+            out_amount_1: Some(dec("18000000")),
+            out_amount_1_usd: None,
+            in_address: "bc1qsender".to_string(),
+            out_address_1: Some("0xreceiver".to_string()),
+            tx_id: "ABCDEF".to_string(),
+            out_asset_2: None,
+            out_amount_2: None,
+            out_amount_2_usd: None,
+            out_address_2: None,
+            status: "success".to_string(),
+            outcome: "success".to_string(),
+            refund_asset: None,
+            refund_amount: None,
+            refund_amount_usd: None,
+            refund_address: None,
+            refund_reason: None,
+            pricing_status: "unpriced".to_string(),
+            asset_kind: "native".to_string(),
+            in_asset_canonical: Some("BTC.BTC".to_string()),
+            out_asset_1_canonical: Some("ETH.ETH".to_string()),
+            out_asset_2_canonical: None,
+            refund_asset_canonical: None,
+            streaming_count: None,
+            streaming_quantity: None,
+            streaming_interval: None,
+            streaming_last_height: None,
+            streaming_deposited_amount: None,
+            streaming_in_amount: None,
+            streaming_out_amount: None,
+            streaming_fill_percent: None,
+            streaming_duration_secs: None,
+            status_history: Vec::new(),
+            valuations: Vec::new(),
+            refund_legs: Vec::new(),
+            quote_values: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_swap_leg_key() {
+        let swap = sample_swap();
+        assert_eq!(swap.leg_key(), "BTC.BTC|ETH.ETH|0xreceiver");
+
+        let affiliate_leg = SwapTransactionFromatted {
+            out_asset_1: Some("THOR.RUNE".to_string()),
+            out_address_1: Some("thor1affiliate".to_string()),
+            ..sample_swap()
+        };
+        assert_ne!(swap.leg_key(), affiliate_leg.leg_key());
+    }
+
+    #[test]
+    fn test_conflict_mode_should_update() {
+        let existing = sample_swap();
+        let richer = SwapTransactionFromatted {
+            out_amount_1_usd: Some(dec("364.9")),
+            ..sample_swap()
+        };
+
+        assert!(!ConflictMode::Skip.should_update(&existing, &richer));
+        assert!(ConflictMode::Overwrite.should_update(&existing, &existing));
+        assert!(ConflictMode::IfMoreComplete.should_update(&existing, &richer));
+        assert!(!ConflictMode::IfMoreComplete.should_update(&richer, &existing));
+        assert!(!ConflictMode::IfMoreComplete.should_update(&existing, &existing));
+    }
+
+    #[test]
+    fn test_upsert_summary_counts() {
+        let mut summary = UpsertSummary::default();
+        summary.record(UpsertOutcome::Inserted);
+        summary.record(UpsertOutcome::Inserted);
+        summary.record(UpsertOutcome::Updated);
+        summary.record(UpsertOutcome::Skipped);
+
+        assert_eq!(
+            summary,
+            UpsertSummary {
+                inserted: 2,
+                updated: 1,
+                skipped: 1
+            }
+        );
     }
 
     #[test]
-    fn test_write_next_page_token_to_file() {
-        let test_token = "test_token";
-        write_next_page_token_to_file(test_token).unwrap();
+    fn test_plan_page_batches_by_outcome() {
+        let stored = sample_swap();
+        let mut existing = HashMap::new();
+        existing.insert((stored.tx_id.clone(), stored.leg_key()), stored);
+
+        let records = vec![
+            // Same leg as the stored row, now with the missing USD value
+            SwapTransactionFromatted {
+                out_amount_1_usd: Some(dec("364.9")),
+                ..sample_swap()
+            },
+            SwapTransactionFromatted {
+                tx_id: "NEWTX".to_string(),
+                ..sample_swap()
+            },
+            // Exact repeat of the stored row
+            sample_swap(),
+        ];
+
+        let plan = plan_page(&existing, &records, ConflictMode::IfMoreComplete);
+        assert_eq!(plan.inserts.len(), 1);
+        assert_eq!(plan.inserts[0].tx_id, "NEWTX");
+        assert_eq!(plan.updates.len(), 1);
+        assert_eq!(plan.updates[0].out_amount_1_usd, Some(dec("364.9")));
+        assert_eq!(
+            plan.summary,
+            UpsertSummary {
+                inserted: 1,
+                updated: 1,
+                skipped: 1
+            }
+        );
+
+        let plan = plan_page(&existing, &records, ConflictMode::Skip);
+        assert_eq!(plan.inserts.len(), 1);
+        assert!(plan.updates.is_empty());
+        assert_eq!(plan.summary.skipped, 2);
+
+        // A new swap repeated within the page is one insert, its earlier copy is skipped
+        let records = vec![
+            SwapTransactionFromatted {
+                tx_id: "NEWTX".to_string(),
+                ..sample_swap()
+            },
+            SwapTransactionFromatted {
+                tx_id: "NEWTX".to_string(),
+                out_amount_1_usd: Some(dec("364.9")),
+                ..sample_swap()
+            },
+        ];
+        let plan = plan_page(&existing, &records, ConflictMode::IfMoreComplete);
+        assert_eq!(plan.inserts.len(), 1);
+        assert_eq!(plan.inserts[0].out_amount_1_usd, Some(dec("364.9")));
+        assert_eq!(
+            plan.summary,
+            UpsertSummary {
+                inserted: 1,
+                updated: 0,
+                skipped: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_plan_page_settles_pending_rows() {
+        // Stored while pending, before any out leg was paid
+        let pending = SwapTransactionFromatted {
+            status: SwapStatus::Pending.as_str().to_string(),
+            out_asset_1: None,
+            out_amount_1: None,
+            out_address_1: None,
+            ..sample_swap()
+        };
+        let pending_key = (pending.tx_id.clone(), pending.leg_key());
+        let mut existing = HashMap::new();
+        existing.insert(pending_key.clone(), pending.clone());
+
+        // The settled copy replaces it whatever the conflict mode
+        let settled = vec![sample_swap()];
+        let plan = plan_page(&existing, &settled, ConflictMode::Skip);
+        assert!(plan.inserts.is_empty());
+        assert_eq!(plan.updates.len(), 1);
+        assert_eq!(plan.replaced, vec![pending_key.clone()]);
+        assert_eq!(plan.summary.updated, 1);
+
+        // Of several settled legs only the first takes the pending row's place
+        let mut second_leg = sample_swap();
+        second_leg.out_address_1 = Some("0xsecondreceiver".to_string());
+        let settled = vec![sample_swap(), second_leg];
+        let plan = plan_page(&existing, &settled, ConflictMode::Skip);
+        assert_eq!(plan.updates.len(), 1);
+        assert_eq!(plan.inserts.len(), 1);
+        assert_eq!(plan.replaced, vec![pending_key.clone()]);
+        assert_eq!(
+            plan.summary,
+            UpsertSummary {
+                inserted: 1,
+                updated: 1,
+                skipped: 0
+            }
+        );
+
+        // A row reconciliation gave up on is still replaced once the swap settles
+        let abandoned = SwapTransactionFromatted {
+            status: SwapStatus::Abandoned.as_str().to_string(),
+            ..pending.clone()
+        };
+        let mut existing = HashMap::new();
+        existing.insert(pending_key.clone(), abandoned);
+        let settled = vec![sample_swap()];
+        let plan = plan_page(&existing, &settled, ConflictMode::Skip);
+        assert_eq!(plan.updates.len(), 1);
+        assert_eq!(plan.replaced, vec![pending_key.clone()]);
+
+        // A pending copy never replaces the settled row
+        let mut existing = HashMap::new();
+        let stored = sample_swap();
+        existing.insert((stored.tx_id.clone(), stored.leg_key()), stored);
+        let records = vec![pending];
+        let plan = plan_page(&existing, &records, ConflictMode::Overwrite);
+        assert!(plan.inserts.is_empty());
+        assert!(plan.updates.is_empty());
+        assert!(plan.replaced.is_empty());
+        assert_eq!(plan.summary.skipped, 1);
+    }
+
+    #[test]
+    fn test_pricing_status_and_repricing_merge() {
+        let mut stored = sample_swap();
+        assert_eq!(stored.missing_usd_values(), 1);
+        assert_eq!(PricingStatus::of(&stored, &[]), PricingStatus::Unpriced);
+
+        // A later attempt that only priced the out leg keeps the stored in value, and only the
+        // provenance of the value it filled in is taken over
+        let valuation = |leg: ValuationLeg, amount_usd: &str| SwapValuation {
+            tx_id: "ABCDEF".to_string(),
+            leg_key: sample_swap().leg_key(),
+            leg: leg.as_str().to_string(),
+            asset: "ETH.ETH".to_string(),
+            provider: "coingecko".to_string(),
+            coin_id: Some("ethereum".to_string()),
+            unit_price_usd: dec("2027.22"),
+            amount_usd: dec(amount_usd),
+            price_timestamp: 1699920000,
+            swap_timestamp: 1700000000,
+            confidence: Confidence::High.as_str().to_string(),
+        };
+        let repriced = SwapTransactionFromatted {
+            in_amount_usd: Some(dec("370")),
+            out_amount_1_usd: Some(dec("364.9")),
+            valuations: vec![
+                valuation(ValuationLeg::In, "370"),
+                valuation(ValuationLeg::Out1, "364.9"),
+            ],
+            ..sample_swap()
+        };
+        stored.fill_usd_values(&repriced);
+        assert_eq!(stored.in_amount_usd, Some(dec("365.12")));
+        assert_eq!(stored.out_amount_1_usd, Some(dec("364.9")));
+        assert_eq!(
+            stored.valuations,
+            vec![valuation(ValuationLeg::Out1, "364.9")]
+        );
+        assert_eq!(PricingStatus::of(&stored, &[]), PricingStatus::Priced);
+
+        // A refunded amount needs its own USD value
+        let refunded = SwapTransactionFromatted {
+            refund_amount: Some(dec("1000")),
+            ..stored.clone()
+        };
+        assert_eq!(PricingStatus::of(&refunded, &[]), PricingStatus::Unpriced);
+
+        // A EUR value missing after a failed rate fetch keeps the swap unpriced, and a later
+        // attempt converts the stored USD value at its rate
+        let currencies = [QuoteCurrency::Eur];
+        assert_eq!(stored.missing_quote_values(&currencies), 1);
+        assert_eq!(
+            PricingStatus::of(&stored, &currencies),
+            PricingStatus::Unpriced
+        );
+        let eur_value = |amount: &str| SwapQuoteValue {
+            tx_id: "ABCDEF".to_string(),
+            leg_key: sample_swap().leg_key(),
+            leg: ValuationLeg::Out1.as_str().to_string(),
+            currency: "eur".to_string(),
+            amount: dec(amount),
+            usd_rate: dec("1.1"),
+        };
+        let repriced = SwapTransactionFromatted {
+            quote_values: vec![eur_value("300")],
+            ..repriced
+        };
+        stored.fill_usd_values(&repriced);
+        assert_eq!(stored.quote_values, vec![eur_value("331.73")]);
+        assert_eq!(
+            PricingStatus::of(&stored, &currencies),
+            PricingStatus::Priced
+        );
+    }
+
+    fn fixture_page() -> ActionsFetchResponse {
+        serde_json::from_str(include_str!("fixtures/actions_page.json")).unwrap()
+    }
+
+    #[test]
+    fn test_swap_status_of_action() {
+        let page = fixture_page();
+        let statuses: Vec<SwapStatus> = page.actions.iter().map(SwapStatus::of).collect();
+
+        assert_eq!(
+            statuses,
+            vec![SwapStatus::Success, SwapStatus::Pending, SwapStatus::Refund]
+        );
+        assert_eq!(SwapStatus::Refund.as_str(), "refund");
+        assert!(SwapStatus::is_settled("refund"));
+        assert!(!SwapStatus::is_settled(SwapStatus::Abandoned.as_str()));
+    }
+
+    #[test]
+    fn test_only_refunds_of_swaps_are_swaps() {
+        let page = fixture_page();
+        assert!(page.actions.iter().all(|action| action.is_swap()));
+
+        let mut refund = page.actions[2].clone();
+        let meta = refund.metadata.refund.as_mut().unwrap();
+        meta.memo = "SWAP:ETH.ETH:0xreceiver".to_string();
+        assert!(refund.is_swap());
+
+        // Failed deposits and unparseable memos are refunded too, they aren't swaps
+        let meta = refund.metadata.refund.as_mut().unwrap();
+        meta.memo = "+:BTC.BTC:thor1depositor".to_string();
+        assert!(!refund.is_swap());
+        let meta = refund.metadata.refund.as_mut().unwrap();
+        meta.memo = "hello".to_string();
+        assert!(!refund.is_swap());
 
-        let read_token = read_next_page_token_from_file().unwrap();
-        assert_eq!(read_token, test_token);
-        fs::remove_file(TOKEN_FILE_PATH).unwrap();
+        let meta = refund.metadata.refund.as_mut().unwrap();
+        meta.txType = Some("swap".to_string());
+        assert!(refund.is_swap());
+    }
+
+    #[test]
+    fn test_swap_outcome_of_action() {
+        let page = fixture_page();
+        assert_eq!(SwapOutcome::of(&page.actions[0]), SwapOutcome::Success);
+        assert_eq!(SwapOutcome::of(&page.actions[2]), SwapOutcome::Refund);
+
+        let (delivered, refunds) = page.actions[2].split_out_legs();
+        assert!(delivered.is_empty());
+        assert_eq!(refunds[0].address, "thor1refundedsender");
+
+        // Part of the inbound asset sent back to the sender alongside the swapped output
+        let mut partial = page.actions[0].clone();
+        let mut returned = partial.in_data[0].clone();
+        returned.coins[0].amount = "4000000".to_string();
+        partial.out_data.push(returned);
+        assert_eq!(SwapOutcome::of(&partial), SwapOutcome::PartialRefund);
+        assert_eq!(SwapOutcome::PartialRefund.as_str(), "partial_refund");
+        assert_eq!(
+            SwapOutcome::parse("Partial_Refund"),
+            Some(SwapOutcome::PartialRefund)
+        );
+        assert_eq!(SwapOutcome::parse("failed"), None);
+
+        // A refund paid out in several txs is stored leg by leg and totalled in its asset
+        let mut split = page.actions[2].clone();
+        split.out_data.push(split.out_data[0].clone());
+        let (_, refunds) = split.split_out_legs();
+        let legs: Vec<SwapRefundLeg> = refunds
+            .iter()
+            .enumerate()
+            .map(|(index, info)| {
+                let (asset, amount, address) = TransactionHandler::parse_leg(info).unwrap();
+                SwapRefundLeg {
+                    tx_id: String::new(),
+                    leg_key: String::new(),
+                    leg_index: index as i32,
+                    asset,
+                    amount,
+                    address,
+                }
+            })
+            .collect();
+        assert_eq!(legs.len(), 2);
+        let (first, total) = refund_total(&legs).unwrap();
+        assert_eq!(total, legs[0].amount + legs[1].amount);
+        assert_eq!(first.address, "thor1refundedsender");
+        assert!(refund_total(&[]).is_none());
+    }
+
+    #[test]
+    fn test_midgard_price_usd_from_metadata() {
+        let page = fixture_page();
+        let swap = &page.actions[0];
+
+        assert_eq!(swap.target_asset(), Some("ETH.ETH"));
+        assert_eq!(swap.midgard_price_usd("BTC.BTC"), Some(36512.47));
+        assert_eq!(swap.midgard_price_usd("ETH.ETH"), Some(2045.18));
+        assert_eq!(swap.midgard_price_usd("THOR.RUNE"), None);
+
+        // Pending swap reports a zero out price, refund actions carry no prices at all
+        assert_eq!(page.actions[1].midgard_price_usd("BTC.BTC"), None);
+        assert_eq!(page.actions[2].midgard_price_usd("THOR.RUNE"), None);
+
+        let mut to_rune = swap.clone();
+        to_rune.pools = vec!["BTC.BTC".to_string()];
+        assert_eq!(to_rune.target_asset(), Some("THOR.RUNE"));
+    }
+
+    #[test]
+    fn test_swap_asset_kind_prices_underlying() {
+        let page = fixture_page();
+        assert_eq!(page.actions[0].asset_kind(), AssetKind::Native);
+
+        // BTC trade account swapped into ETH synths, still routed through the L1 pools
+        let mut swap = page.actions[0].clone();
+        swap.in_data[0].coins[0].asset = "BTC~BTC".to_string();
+        swap.out_data[0].coins[0].asset = "ETH/ETH".to_string();
+        assert_eq!(swap.asset_kind(), AssetKind::Trade);
+        assert_eq!(swap.target_asset(), Some("ETH.ETH"));
+        assert_eq!(swap.midgard_price_usd("BTC.BTC"), Some(36512.47));
+        assert_eq!(swap.midgard_price_usd("ETH.ETH"), Some(2045.18));
+
+        swap.in_data[0].coins[0].asset = "BTC.BTC".to_string();
+        assert_eq!(swap.asset_kind(), AssetKind::Synth);
+        swap.out_data[0].coins[0].asset = "ETH-ETH".to_string();
+        assert_eq!(swap.asset_kind(), AssetKind::Secured);
+    }
+
+    #[test]
+    fn test_streaming_swap_details() {
+        let page = fixture_page();
+        assert_eq!(page.actions[0].streaming_details(), None);
+
+        let mut swap = page.actions[0].clone();
+        swap.height = "13500000".to_string();
+        let metadata = swap.metadata.swap.as_mut().unwrap();
+        metadata.isStreamingSwap = true;
+        metadata.streamingSwapMeta = Some(
+            serde_json::from_str(
+                r#"{
+                    "count": "8",
+                    "quantity": "10",
+                    "interval": "3",
+                    "lastHeight": "13500021",
+                    "outEstimation": "178000000",
+                    "depositedCoin": { "amount": "10000000", "asset": "BTC.BTC" },
+                    "inCoin": { "amount": "8000000", "asset": "BTC.BTC" },
+                    "outCoin": { "amount": "142400000", "asset": "ETH.ETH" }
+                }"#,
+            )
+            .unwrap(),
+        );
+
+        let details = swap.streaming_details().unwrap();
+        assert_eq!(
+            (details.count, details.quantity, details.interval),
+            (Some(8), Some(10), Some(3))
+        );
+        assert_eq!(details.last_height, Some(13500021));
+        assert_eq!(details.deposited_amount, Some(dec("10000000")));
+        assert_eq!(details.out_amount, Some(dec("142400000")));
+        assert_eq!(details.fill_percent, Some(dec("80")));
+        assert_eq!(details.duration_secs, Some(21 * 6));
+
+        // Without the inbound height the sub-swap spacing is used
+        swap.height = String::new();
+        assert_eq!(
+            swap.streaming_details().unwrap().duration_secs,
+            Some(7 * 3 * 6)
+        );
+
+        let mut record = sample_swap();
+        record.set_streaming_details(Some(&details));
+        assert_eq!(record.streaming_quantity, Some(10));
+        assert_eq!(record.streaming_fill_percent, Some(dec("80")));
+        record.set_streaming_details(None);
+        assert_eq!(record.streaming_count, None);
+
+        // Flagged off, the meta Midgard attaches to single block swaps is ignored
+        swap.metadata.swap.as_mut().unwrap().isStreamingSwap = false;
+        assert_eq!(swap.streaming_details(), None);
+
+        // A page with a partial meta still decodes, what can't be derived is left out
+        let mut value: serde_json::Value =
+            serde_json::from_str(include_str!("fixtures/actions_page.json")).unwrap();
+        value["actions"][0]["metadata"]["swap"]["isStreamingSwap"] = true.into();
+        value["actions"][0]["metadata"]["swap"]["streamingSwapMeta"] = serde_json::json!({
+            "quantity": "10",
+            "inCoin": { "amount": "8000000", "asset": "BTC.BTC" }
+        });
+        let page: ActionsFetchResponse = serde_json::from_value(value).unwrap();
+        let mut swap = page.actions[0].clone();
+        swap.height = String::new();
+        let details = swap.streaming_details().unwrap();
+        assert_eq!(details.quantity, Some(10));
+        assert_eq!(details.in_amount, Some(dec("8000000")));
+        assert_eq!(details.count, None);
+        assert_eq!(details.fill_percent, None);
+        assert_eq!(details.duration_secs, None);
+        let mut record = sample_swap();
+        record.set_streaming_details(Some(&details));
+        assert_eq!(record.streaming_quantity, Some(10));
+        assert_eq!(record.streaming_fill_percent, None);
+        assert_eq!(record.streaming_duration_secs, None);
+
+        assert_eq!(fill_percent(dec("1"), dec("3")), Some(dec("33.33")));
+        assert_eq!(fill_percent(dec("1"), Decimal::ZERO), None);
+    }
+
+    #[test]
+    fn test_asset_kind_filter() {
+        assert_eq!(
+            AssetKind::parse_list("native, Trade,native").unwrap(),
+            vec![AssetKind::Native, AssetKind::Trade]
+        );
+        assert!(matches!(
+            AssetKind::parse_list("native,notrade"),
+            Err(AssetError::UnknownKind(_))
+        ));
+        for kind in AssetKind::ALL {
+            assert_eq!(AssetKind::parse(kind.as_str()), Some(kind));
+        }
+
+        let synth = Asset::parse("ETH/USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7").unwrap();
+        assert_eq!(
+            synth.underlying().to_string(),
+            "ETH.USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7"
+        );
+        assert_eq!(underlying_asset("btc~btc"), "BTC.BTC");
+        assert_eq!(underlying_asset("not an asset"), "not an asset");
+    }
+
+    #[test]
+    fn test_valuation_mode_parse() {
+        assert_eq!(
+            ValuationMode::parse("midgard"),
+            Some(ValuationMode::Midgard)
+        );
+        assert_eq!(
+            ValuationMode::parse("Midgard_Fallback"),
+            Some(ValuationMode::MidgardWithFallback)
+        );
+        assert_eq!(
+            ValuationMode::parse("cross_check"),
+            Some(ValuationMode::CrossCheck)
+        );
+        assert_eq!(
+            ValuationMode::parse("coingecko"),
+            Some(ValuationMode::CoinGecko)
+        );
+        assert_eq!(ValuationMode::parse("binance"), None);
+    }
+
+    #[test]
+    fn coingecko_is_only_required_by_settings_that_use_it() {
+        let midgard_only = ValuationConfig {
+            mode: ValuationMode::Midgard,
+            providers: ValuationMode::Midgard.default_providers(),
+            currencies: vec![QuoteCurrency::Usd, QuoteCurrency::Btc, QuoteCurrency::Rune],
+            ..ValuationConfig::default()
+        };
+        assert_eq!(midgard_only.coingecko_required_by(), None);
+
+        let fiat = ValuationConfig {
+            currencies: vec![QuoteCurrency::Eur],
+            ..midgard_only.clone()
+        };
+        assert!(fiat.coingecko_required_by().unwrap().contains("eur"));
+
+        let cross_check = ValuationConfig {
+            mode: ValuationMode::CrossCheck,
+            ..midgard_only.clone()
+        };
+        assert!(cross_check
+            .coingecko_required_by()
+            .unwrap()
+            .contains("cross_check"));
+
+        let fallback = ValuationConfig {
+            currencies: Vec::new(),
+            ..ValuationConfig::default()
+        };
+        assert!(fallback
+            .coingecko_required_by()
+            .unwrap()
+            .contains("coingecko"));
+
+        assert!((relative_deviation(105.0, 100.0) - 0.05).abs() < 1e-9);
+        assert_eq!(relative_deviation(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_provider_kind_parse_list() {
+        assert_eq!(
+            ProviderKind::parse_list("midgard, pool_depth,file,coingecko").unwrap(),
+            vec![
+                ProviderKind::MidgardMetadata,
+                ProviderKind::PoolDepth,
+                ProviderKind::StaticFile,
+                ProviderKind::CoinGecko
+            ]
+        );
+        assert!(matches!(
+            ProviderKind::parse_list("midgard,binance"),
+            Err(OracleError::Config(_))
+        ));
+        assert_eq!(
+            ValuationMode::MidgardWithFallback.default_providers(),
+            vec![ProviderKind::MidgardMetadata, ProviderKind::CoinGecko]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_static_price_oracle() {
+        let oracle = StaticPriceOracle::from_csv(
+            "asset,timestamp,price_usd\nBTC.BTC,1700000000,36500.5\nBTC.BTC,1700003600,36600\n",
+            86400,
+        )
+        .unwrap();
+
+        let quote = oracle.price_usd("BTC.BTC", 1700001000).await.unwrap();
+        assert_eq!(quote.price_usd, 36500.5);
+        assert_eq!(quote.timestamp, 1700000000);
+        assert_eq!(quote.provider, "file");
+
+        assert!(oracle.price_usd("BTC.BTC", 1699999999).await.is_err());
+        assert!(oracle
+            .price_usd("BTC.BTC", 1700003600 + 86401)
+            .await
+            .is_err());
+        assert!(oracle.price_usd("ETH.ETH", 1700001000).await.is_err());
+        assert!(StaticPriceOracle::from_csv("BTC.BTC,not-a-time,1", 86400).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_composite_oracle_tries_providers_in_order() {
+        let page = fixture_page();
+        let swap = &page.actions[0];
+        let oracle = CompositeOracle::new(vec![
+            PriceProvider::MidgardMetadata(MidgardMetadataOracle),
+            PriceProvider::StaticFile(
+                StaticPriceOracle::from_csv(
+                    "BTC.BTC,1600000000,1.0\nTHOR.RUNE,1700000000,5.2",
+                    86400,
+                )
+                .unwrap(),
+            ),
+        ]);
+
+        // The swap doesn't trade RUNE, its price comes from the file
+        let quote = oracle
+            .price_for(Some(swap), "THOR.RUNE", 1700000000)
+            .await
+            .unwrap();
+        assert_eq!((quote.provider, quote.price_usd), ("file", 5.2));
+
+        let quote = oracle
+            .price_for(Some(swap), "BTC.BTC", 1700000000)
+            .await
+            .unwrap();
+        assert_eq!((quote.provider, quote.price_usd), ("midgard", 36512.47));
+        let quote = oracle
+            .price_for(Some(swap), "ETH.ETH", 1700000000)
+            .await
+            .unwrap();
+        assert_eq!(quote.price_usd, 2045.18);
+
+        // Another swap's prices are never picked up, and without a swap there are none
+        assert!(matches!(
+            oracle
+                .price_for(Some(&page.actions[2]), "ETH.ETH", 1700000000)
+                .await,
+            Err(OracleError::NoPrice(_))
+        ));
+        assert!(matches!(
+            oracle.price_usd("ETH.ETH", 1700000000).await,
+            Err(OracleError::NoPrice(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_price_cache_evicts_least_recently_used() {
+        let cache = PriceCache::new(2, None);
+        cache.put("bitcoin", "14-11-2023", 36512.47).await;
+        cache.put("ethereum", "14-11-2023", 2045.18).await;
+
+        // Touch bitcoin so ethereum is the one evicted
+        assert_eq!(cache.get("bitcoin", "14-11-2023").await, Some(36512.47));
+        cache.put("thorchain", "14-11-2023", 5.2).await;
+
+        assert_eq!(cache.get("ethereum", "14-11-2023").await, None);
+        assert_eq!(cache.get("bitcoin", "14-11-2023").await, Some(36512.47));
+        assert_eq!(cache.get("bitcoin", "15-11-2023").await, None);
+    }
+
+    #[test]
+    fn test_coin_registry_lookup() {
+        let mut registry =
+            CoinRegistry::from_json(include_str!("../../data/coingecko_ids.json")).unwrap();
+        assert!(registry.version >= 1);
+
+        // Same ticker on different chains and contracts resolves per asset
+        let eth_usdc = registry
+            .lookup("ETH.USDC-0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")
+            .unwrap();
+        assert_eq!(eth_usdc.coin_id, "usd-coin");
+        assert_eq!(eth_usdc.source, CoinIdSource::Curated);
+        assert_eq!(eth_usdc.confidence(), Confidence::High);
+        assert_eq!(registry.lookup("THOR.RUNE").unwrap().coin_id, "thorchain");
+        assert!(registry.lookup("ETH.USDC-0XDEADBEEF").is_none());
+
+        registry.set_override("thor.rune", "thorchain-erc20");
+        let rune = registry.lookup("THOR.RUNE").unwrap();
+        assert_eq!(rune.coin_id, "thorchain-erc20");
+        assert_eq!(rune.source, CoinIdSource::Override);
+
+        // Stored mappings are keyed by one spelling of each asset
+        assert_eq!(CoinRegistry::normalize(" thor.rune "), "THOR.RUNE");
+        assert_eq!(
+            CoinRegistry::normalize("eth.usdc-0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+            CoinRegistry::normalize("ETH.USDC-0XA0B86991C6218B36C1D19D4A2E9EB0CE3606EB48")
+        );
+    }
+
+    #[test]
+    fn test_coin_id_source_round_trip() {
+        for source in [
+            CoinIdSource::Override,
+            CoinIdSource::Curated,
+            CoinIdSource::Search,
+        ] {
+            assert_eq!(CoinIdSource::parse(source.as_str()), Some(source));
+        }
+        assert_eq!(CoinIdSource::parse("manual"), None);
+    }
+
+    #[test]
+    fn test_interpolate_price() {
+        let points = vec![(1000, 10.0), (4600, 20.0), (8200, 14.0), (30000, 50.0)];
+        let max_gap = ChartResolution::Hourly.max_gap();
+
+        assert_eq!(
+            interpolate_price(&points, 4600, max_gap),
+            Some((20.0, 4600))
+        );
+        // A quarter of the way from 10 to 20, nearest point is the first one
+        assert_eq!(
+            interpolate_price(&points, 1900, max_gap),
+            Some((12.5, 1000))
+        );
+        assert_eq!(
+            interpolate_price(&points, 7300, max_gap),
+            Some((15.5, 8200))
+        );
+
+        // Across a gap wider than max_gap only a close enough neighbour counts
+        assert_eq!(
+            interpolate_price(&points, 9000, max_gap),
+            Some((14.0, 8200))
+        );
+        assert_eq!(interpolate_price(&points, 20000, max_gap), None);
+
+        // Before the first and after the last point
+        assert_eq!(interpolate_price(&points, 0, max_gap), Some((10.0, 1000)));
+        assert_eq!(interpolate_price(&points, 40000, max_gap), None);
+        assert_eq!(interpolate_price(&[], 1000, max_gap), None);
+
+        assert_eq!(
+            ChartResolution::parse("5min"),
+            Some(ChartResolution::FiveMinute)
+        );
+        // 5 minute points only exist for the last day, older swaps are priced hourly
+        let now = 1700000000;
+        let five_minute = ChartResolution::FiveMinute;
+        assert_eq!(five_minute.at(now - 600, now), ChartResolution::FiveMinute);
+        assert_eq!(
+            five_minute.at(now - 3 * 86400, now),
+            ChartResolution::Hourly
+        );
+        assert_eq!(
+            ChartResolution::Hourly.at(now - 600, now),
+            ChartResolution::Hourly
+        );
+        assert_eq!(
+            interpolate_price(&points, 2800, five_minute.max_gap()),
+            None
+        );
+        assert_eq!(
+            interpolate_price(&points, 2800, ChartResolution::Hourly.max_gap()),
+            Some((15.0, 1000))
+        );
+        assert_eq!(
+            ProviderKind::parse("coingecko_intraday"),
+            Some(ProviderKind::CoinGeckoIntraday)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_collapses_concurrent_calls() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let flights: SingleFlight<String, u64> = SingleFlight::default();
+        let calls = AtomicUsize::new(0);
+        let fetch = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            42
+        };
+
+        let (a, b, c) = tokio::join!(
+            flights.run("bitcoin".to_string(), fetch),
+            flights.run("bitcoin".to_string(), fetch),
+            flights.run("bitcoin".to_string(), fetch),
+        );
+        assert_eq!((a, b, c), (42, 42, 42));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Once finished the key is free again, and other keys never wait on each other
+        let (d, e) = tokio::join!(
+            flights.run("bitcoin".to_string(), fetch),
+            flights.run("ethereum".to_string(), fetch),
+        );
+        assert_eq!((d, e), (42, 42));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_budget() {
+        assert_eq!(
+            ApiPlan::parse("Pro").map(|plan| plan.calls_per_minute()),
+            Some(500)
+        );
+        assert_eq!(
+            ApiPlan::parse("demo").map(|plan| plan.calls_per_minute()),
+            Some(30)
+        );
+        assert_eq!(ApiPlan::parse("enterprise"), None);
+
+        let limiter = RateLimiter::new(30);
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        limiter.acquire_with(Priority::High).await;
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.calls_per_minute, 30);
+        assert!(metrics.remaining >= 26.0 && metrics.remaining < 27.0);
+        assert_eq!((metrics.granted_low, metrics.granted_high), (3, 1));
+        assert_eq!(metrics.throttled, 0);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_serves_tail_before_backfill() {
+        use std::sync::Mutex;
+
+        // 20 tokens a second, drained so every request below has to wait for a refill
+        let limiter = RateLimiter::new(1200);
+        for _ in 0..1200 {
+            limiter.acquire_with(Priority::High).await;
+        }
+
+        let order = Mutex::new(Vec::new());
+        let backfill = async {
+            limiter.acquire().await;
+            order.lock().unwrap().push("backfill");
+        };
+        let tail = with_priority(Priority::High, async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            limiter.acquire().await;
+            order.lock().unwrap().push("tail");
+        });
+        tokio::join!(backfill, tail);
+
+        assert_eq!(*order.lock().unwrap(), vec!["tail", "backfill"]);
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.throttled, 2);
+        assert_eq!(metrics.high_priority_waiting, 0);
+    }
+
+    #[test]
+    fn test_pool_depth_price() {
+        // 100 BTC against 2,500,000 RUNE at $1.50 per RUNE
+        let price = pool_price_usd(100.0e8, 2_500_000.0e8, 1.5).unwrap();
+        assert!((price - 37500.0).abs() < 1e-6);
+
+        // An empty pool or a missing RUNE price gives no price rather than zero or infinity
+        assert_eq!(pool_price_usd(0.0, 2_500_000.0e8, 1.5), None);
+        assert_eq!(pool_price_usd(100.0e8, 0.0, 1.5), None);
+        assert_eq!(pool_price_usd(100.0e8, 2_500_000.0e8, 0.0), None);
+
+        assert_eq!(HistoryInterval::parse("Hour"), Some(HistoryInterval::Hour));
+        assert_eq!(HistoryInterval::parse("week"), None);
+        assert_eq!(HistoryInterval::Day.secs(), 86400);
+    }
+
+    #[test]
+    fn test_quote_currency_conversion() {
+        assert_eq!(
+            QuoteCurrency::parse_list("EUR, btc,usd,eur").unwrap(),
+            vec![QuoteCurrency::Eur, QuoteCurrency::Btc]
+        );
+        assert!(QuoteCurrency::parse_list("eur,jpy").is_err());
+
+        // Fiat rounds to cents, crypto to 8 decimals
+        assert_eq!(
+            QuoteCurrency::Eur.convert_usd(dec("365.12"), dec("1.0712")),
+            Some(dec("340.85"))
+        );
+        assert_eq!(
+            QuoteCurrency::Btc.convert_usd(dec("365.12"), dec("36512")),
+            Some(dec("0.01"))
+        );
+        assert_eq!(
+            QuoteCurrency::Rune.convert_usd(dec("365.12"), Decimal::ZERO),
+            None
+        );
+
+        let valuation = SwapValuation {
+            tx_id: "ABCDEF".to_string(),
+            leg_key: sample_swap().leg_key(),
+            leg: ValuationLeg::In.as_str().to_string(),
+            asset: "BTC.BTC".to_string(),
+            provider: "midgard".to_string(),
+            coin_id: None,
+            unit_price_usd: dec("36512"),
+            amount_usd: dec("365.12"),
+            price_timestamp: 1700000000,
+            swap_timestamp: 1700000000,
+            confidence: Confidence::High.as_str().to_string(),
+        };
+        let values = quote_values(
+            &[valuation],
+            &[
+                (QuoteCurrency::Eur, dec("1.0712")),
+                (QuoteCurrency::Rune, Decimal::ZERO),
+            ],
+        );
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].currency, "eur");
+        assert_eq!(values[0].leg, "in");
+        assert_eq!(values[0].amount, dec("340.85"));
+        assert_eq!(values[0].tx_id, "ABCDEF");
     }
 }
@@ -1,24 +1,48 @@
+pub mod actions_source;
+pub mod asset;
+pub mod coin_registry;
 pub mod coingecko;
 pub mod cron;
+pub mod currency;
 pub mod midgard;
+pub mod price_cache;
+pub mod price_oracle;
+pub mod rate_limiter;
+pub mod retry;
+pub mod single_flight;
 pub mod transaction_handler;
 
 use chrono::{NaiveDate, ParseError, TimeZone, Utc};
-use regex::Regex;
+use rust_decimal::prelude::{Decimal, FromPrimitive};
 use std::error::Error;
-use std::fs::{self, OpenOptions};
-use std::io::{self, Write};
+use std::fs;
+use std::io;
 use std::num::{ParseFloatError, ParseIntError};
 use std::path::Path;
+use std::str::FromStr;
 
-pub fn convert_to_standard_unit(amount: f64, decimals: u32) -> f64 {
-    let divisor = 10u64.pow(decimals);
-    amount as f64 / divisor as f64
+pub fn convert_to_standard_unit(amount: Decimal, decimals: u32) -> Decimal {
+    let divisor = Decimal::from(10u64.pow(decimals));
+    amount / divisor
 }
 
-pub fn calculate_transaction_amount(amount: f64, price: f64) -> f64 {
+pub fn calculate_transaction_amount(amount: Decimal, price: Decimal) -> Decimal {
     amount * price
 }
+
+// Midgard amounts are integer base units, parsed exactly
+pub fn parse_amount(input: &str) -> Result<Decimal, rust_decimal::Error> {
+    Decimal::from_str(input.trim())
+}
+
+// Prices arrive as floats from the providers, this is where they become fixed point, to the
+// 12 decimals they are stored with
+pub fn price_to_decimal(price: f64) -> Option<Decimal> {
+    Decimal::from_f64(price)
+        .map(|price| price.round_dp(12))
+        .filter(|price| price.is_sign_positive() && !price.is_zero())
+}
+
 pub fn convert_nano_to_sec(nano_str: &str) -> String {
     let nanoseconds: i64 = nano_str.parse().expect("Invalid date string");
     let seconds = nanoseconds / 1_000_000_000;
@@ -26,14 +50,15 @@ pub fn convert_nano_to_sec(nano_str: &str) -> String {
 }
 
 pub fn parse_f64(input: &str) -> Result<f64, ParseFloatError> {
-    input.parse::<f64>().map_err(|e| e)
+    input.parse::<f64>()
 }
 
 pub fn parse_u64(input: &str) -> Result<u64, ParseIntError> {
-    input.parse::<u64>().map_err(|e| e)
+    input.parse::<u64>()
 }
 
-pub fn format_epoch_timestamp(epoch_nanos: &str) -> Result<(String, String), Box<dyn Error>> {
+// UTC day and hh:mmam time of a nanosecond timestamp
+pub fn format_epoch_timestamp(epoch_nanos: &str) -> Result<(NaiveDate, String), Box<dyn Error>> {
     let nanos = epoch_nanos.parse::<i64>()?;
     let seconds = nanos / 1_000_000_000;
     let nanoseconds = (nanos % 1_000_000_000) as u32;
@@ -43,25 +68,9 @@ pub fn format_epoch_timestamp(epoch_nanos: &str) -> Result<(String, String), Box
         .single()
         .ok_or("Invalid timestamp")?;
 
-    let formatted_date = datetime.format("%d-%m-%Y").to_string();
     let formatted_time = datetime.format("%I:%M%p").to_string().to_lowercase();
 
-    Ok((formatted_date, formatted_time))
-}
-
-pub fn coin_name_from_pool(pool_name: &str) -> Option<String> {
-    let re = Regex::new(r"[./~\-_|:;,+*^$!?]").unwrap();
-    let parts: Vec<&str> = re.split(pool_name).collect();
-    parts.get(1).map(|s| s.to_string())
-}
-
-pub fn asset_name_from_pool(pool_name: &str) -> Option<String> {
-    let re = Regex::new(r"[./~\-_|:;,+*^$!?]").unwrap();
-    let mut parts = re.split(pool_name);
-    match (parts.next(), parts.next()) {
-        (Some(first), Some(second)) => Some(format!("{}.{}", first, second)),
-        _ => None,
-    }
+    Ok((datetime.date_naive(), formatted_time))
 }
 
 pub fn format_date_for_sql(date_str: &str) -> Result<String, ParseError> {
@@ -69,6 +78,7 @@ pub fn format_date_for_sql(date_str: &str) -> Result<String, ParseError> {
     Ok(date.format("%Y-%m-%d").to_string())
 }
 
+// Legacy location of the backfill cursor, only read to seed the ingestion_checkpoints table
 const TOKEN_FILE_PATH: &str = "next_page_token.txt";
 
 pub fn read_next_page_token_from_file() -> io::Result<String> {
@@ -78,13 +88,3 @@ pub fn read_next_page_token_from_file() -> io::Result<String> {
         Ok(String::from("170981189000000012"))
     }
 }
-
-pub fn write_next_page_token_to_file(token: &str) -> io::Result<()> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(TOKEN_FILE_PATH)?;
-    file.write_all(token.as_bytes())?;
-    Ok(())
-}
@@ -0,0 +1,33 @@
+use crate::models::actions_model::ActionsFetchResponse;
+use std::fmt::Debug;
+use std::future::Future;
+
+// A pageable feed of swap actions. Tokens are opaque to the ingestion pipeline, only the
+// source that issued them has to understand them.
+pub trait ActionsSource {
+    type Error: Debug;
+
+    // Stable identifier used to key this source's ingestion checkpoints
+    fn name(&self) -> &str;
+
+    fn fetch_next_page(
+        &self,
+        next_page_token: &str,
+    ) -> impl Future<Output = Result<ActionsFetchResponse, Self::Error>> + Send;
+
+    fn fetch_prev_page(
+        &self,
+        prev_page_token: &str,
+    ) -> impl Future<Output = Result<ActionsFetchResponse, Self::Error>> + Send;
+
+    fn fetch_from_timestamp(
+        &self,
+        timestamp: &str,
+    ) -> impl Future<Output = Result<ActionsFetchResponse, Self::Error>> + Send;
+
+    // Every action Midgard reports for one inbound tx, whatever its status
+    fn fetch_by_tx_id(
+        &self,
+        tx_id: &str,
+    ) -> impl Future<Output = Result<ActionsFetchResponse, Self::Error>> + Send;
+}
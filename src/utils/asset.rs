@@ -0,0 +1,199 @@
+use std::fmt;
+use std::str::FromStr;
+
+// How an asset is held, told apart by the separator between chain and symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AssetKind {
+    // CHAIN.SYMBOL, the asset on its own chain (and THOR.RUNE)
+    Native,
+    // CHAIN/SYMBOL
+    Synth,
+    // CHAIN~SYMBOL
+    Trade,
+    // CHAIN-SYMBOL
+    Secured,
+}
+
+impl AssetKind {
+    pub const ALL: [AssetKind; 4] = [
+        AssetKind::Native,
+        AssetKind::Synth,
+        AssetKind::Trade,
+        AssetKind::Secured,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "native" => Some(AssetKind::Native),
+            "synth" => Some(AssetKind::Synth),
+            "trade" => Some(AssetKind::Trade),
+            "secured" => Some(AssetKind::Secured),
+            _ => None,
+        }
+    }
+
+    // Comma separated kind names, duplicates are dropped
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, AssetError> {
+        let mut kinds = Vec::new();
+        for name in value.split(',').filter(|name| !name.trim().is_empty()) {
+            let kind = Self::parse(name)
+                .ok_or_else(|| AssetError::UnknownKind(name.trim().to_string()))?;
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        Ok(kinds)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssetKind::Native => "native",
+            AssetKind::Synth => "synth",
+            AssetKind::Trade => "trade",
+            AssetKind::Secured => "secured",
+        }
+    }
+
+    pub fn separator(&self) -> char {
+        match self {
+            AssetKind::Native => '.',
+            AssetKind::Synth => '/',
+            AssetKind::Trade => '~',
+            AssetKind::Secured => '-',
+        }
+    }
+
+    fn from_separator(separator: char) -> Option<Self> {
+        match separator {
+            '.' => Some(AssetKind::Native),
+            '/' => Some(AssetKind::Synth),
+            '~' => Some(AssetKind::Trade),
+            '-' => Some(AssetKind::Secured),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetError {
+    MissingSeparator(String),
+    InvalidChain(String),
+    InvalidTicker(String),
+    InvalidContract(String),
+    UnknownKind(String),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::MissingSeparator(asset) => {
+                write!(f, "Asset has no chain separator: {}", asset)
+            }
+            AssetError::InvalidChain(asset) => write!(f, "Invalid chain in asset: {}", asset),
+            AssetError::InvalidTicker(asset) => write!(f, "Invalid ticker in asset: {}", asset),
+            AssetError::InvalidContract(asset) => {
+                write!(f, "Invalid contract in asset: {}", asset)
+            }
+            AssetError::UnknownKind(kind) => write!(f, "Unknown asset kind: {}", kind),
+        }
+    }
+}
+
+// A THORChain asset such as ETH.USDT-0XDAC17F958D2EE523A2206206994597C13D831EC7: the chain,
+// the kind separator, then the symbol, which is the ticker with an optional -CONTRACT suffix.
+// Parsing upper-cases, and the canonical Display form always parses back to the same asset.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Asset {
+    pub chain: String,
+    pub symbol: String,
+    pub ticker: String,
+    pub contract: Option<String>,
+    pub kind: AssetKind,
+}
+
+fn is_identifier(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+impl Asset {
+    pub fn parse(value: &str) -> Result<Self, AssetError> {
+        let value = value.trim().to_uppercase();
+        let (index, separator) = value
+            .char_indices()
+            .find(|(_, c)| AssetKind::from_separator(*c).is_some())
+            .ok_or_else(|| AssetError::MissingSeparator(value.clone()))?;
+        let kind = AssetKind::from_separator(separator).unwrap();
+
+        let chain = &value[..index];
+        let symbol = &value[index + 1..];
+        if !is_identifier(chain) {
+            return Err(AssetError::InvalidChain(value.clone()));
+        }
+
+        let (ticker, contract) = match symbol.split_once('-') {
+            Some((ticker, contract)) => (ticker, Some(contract)),
+            None => (symbol, None),
+        };
+        if !is_identifier(ticker) {
+            return Err(AssetError::InvalidTicker(value.clone()));
+        }
+        if contract.is_some_and(|contract| !is_identifier(contract)) {
+            return Err(AssetError::InvalidContract(value.clone()));
+        }
+
+        Ok(Self {
+            chain: chain.to_string(),
+            symbol: symbol.to_string(),
+            ticker: ticker.to_string(),
+            contract: contract.map(|contract| contract.to_string()),
+            kind,
+        })
+    }
+
+    pub fn is_rune(&self) -> bool {
+        self.chain == "THOR" && self.ticker == "RUNE" && self.contract.is_none()
+    }
+
+    // The L1 asset a synth, trade or secured asset is backed by, and priced as
+    pub fn underlying(&self) -> Self {
+        Self {
+            kind: AssetKind::Native,
+            ..self.clone()
+        }
+    }
+
+    // CHAIN.TICKER whatever the kind, the form swap rows have always stored and their leg keys
+    // are built from
+    pub fn short_name(&self) -> String {
+        format!("{}.{}", self.chain, self.ticker)
+    }
+}
+
+// CHAIN.TICKER form of an asset string, or the string itself when it doesn't parse
+pub fn short_asset_name(value: &str) -> String {
+    Asset::parse(value)
+        .map(|asset| asset.short_name())
+        .unwrap_or_else(|_| value.to_string())
+}
+
+// Canonical name of the L1 asset behind an asset string, or the string itself when it doesn't
+// parse so callers can still report it
+pub fn underlying_asset(value: &str) -> String {
+    Asset::parse(value)
+        .map(|asset| asset.underlying().to_string())
+        .unwrap_or_else(|_| value.to_string())
+}
+
+impl FromStr for Asset {
+    type Err = AssetError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse(value)
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.chain, self.kind.separator(), self.symbol)
+    }
+}
@@ -0,0 +1,171 @@
+use super::asset::Asset;
+use dotenv::dotenv;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{env, fmt, fs};
+
+// Curated mapping shipped with the binary, bump its version whenever an entry changes
+const CURATED_REGISTRY: &str = include_str!("../../data/coingecko_ids.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinIdSource {
+    Override,
+    Curated,
+    Search,
+}
+
+impl CoinIdSource {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "override" => Some(CoinIdSource::Override),
+            "curated" => Some(CoinIdSource::Curated),
+            "search" => Some(CoinIdSource::Search),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoinIdSource::Override => "override",
+            CoinIdSource::Curated => "curated",
+            CoinIdSource::Search => "search",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    High,
+    Low,
+}
+
+impl Confidence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Confidence::High => "high",
+            Confidence::Low => "low",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoinIdMatch {
+    pub coin_id: String,
+    pub source: CoinIdSource,
+}
+
+impl CoinIdMatch {
+    // A search hit is only CoinGecko's best guess for a ticker, it may be another chain's
+    // token or an unrelated coin with the same symbol
+    pub fn confidence(&self) -> Confidence {
+        match self.source {
+            CoinIdSource::Search => Confidence::Low,
+            CoinIdSource::Override | CoinIdSource::Curated => Confidence::High,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Read(String),
+    Parse(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Read(err) => write!(f, "Error reading coin registry: {}", err),
+            RegistryError::Parse(err) => write!(f, "Error parsing coin registry: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    version: u32,
+    assets: HashMap<String, String>,
+}
+
+// THORChain asset (CHAIN.SYMBOL[-CONTRACT]) to CoinGecko id. Admin overrides win over the
+// curated entries.
+#[derive(Debug, Default)]
+pub struct CoinRegistry {
+    pub version: u32,
+    curated: HashMap<String, String>,
+    overrides: HashMap<String, String>,
+}
+
+impl CoinRegistry {
+    pub fn from_json(curated: &str) -> Result<Self, RegistryError> {
+        let file = Self::parse(curated)?;
+        Ok(Self {
+            version: file.version,
+            curated: file.assets,
+            overrides: HashMap::new(),
+        })
+    }
+
+    // Reads COIN_REGISTRY_FILE to replace the bundled registry and COIN_REGISTRY_OVERRIDES for
+    // admin overrides, both in the same {"version", "assets"} format
+    pub fn from_env() -> Result<Self, RegistryError> {
+        dotenv().ok();
+        let mut registry = match env::var("COIN_REGISTRY_FILE") {
+            Ok(path) => Self::from_json(&Self::read(&path)?)?,
+            Err(_) => Self::from_json(CURATED_REGISTRY)?,
+        };
+        if let Ok(path) = env::var("COIN_REGISTRY_OVERRIDES") {
+            for (asset, coin_id) in Self::parse(&Self::read(&path)?)?.assets {
+                registry.set_override(&asset, &coin_id);
+            }
+        }
+        println!(
+            "Loaded coin registry v{} ({} curated, {} overridden)",
+            registry.version,
+            registry.curated.len(),
+            registry.overrides.len()
+        );
+        Ok(registry)
+    }
+
+    fn read(path: &str) -> Result<String, RegistryError> {
+        fs::read_to_string(path).map_err(|err| RegistryError::Read(format!("{}: {}", path, err)))
+    }
+
+    fn parse(contents: &str) -> Result<RegistryFile, RegistryError> {
+        let mut file: RegistryFile =
+            serde_json::from_str(contents).map_err(|err| RegistryError::Parse(err.to_string()))?;
+        file.assets = file
+            .assets
+            .into_iter()
+            .map(|(asset, coin_id)| (Self::normalize(&asset), coin_id))
+            .collect();
+        Ok(file)
+    }
+
+    // Midgard reports contracts upper-cased, accept entries in any case
+    pub fn normalize(asset: &str) -> String {
+        Asset::parse(asset)
+            .map(|asset| asset.to_string())
+            .unwrap_or_else(|_| asset.trim().to_uppercase())
+    }
+
+    pub fn set_override(&mut self, asset: &str, coin_id: &str) {
+        self.overrides
+            .insert(Self::normalize(asset), coin_id.to_string());
+    }
+
+    pub fn lookup(&self, asset: &str) -> Option<CoinIdMatch> {
+        let asset = Self::normalize(asset);
+        if let Some(coin_id) = self.overrides.get(&asset) {
+            return Some(CoinIdMatch {
+                coin_id: coin_id.clone(),
+                source: CoinIdSource::Override,
+            });
+        }
+        self.curated.get(&asset).map(|coin_id| CoinIdMatch {
+            coin_id: coin_id.clone(),
+            source: CoinIdSource::Curated,
+        })
+    }
+}
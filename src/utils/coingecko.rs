@@ -1,80 +1,371 @@
-use crate::models::{CoinSearchResponse, PriceFetchResponse};
+use super::asset::Asset;
+use super::coin_registry::{CoinIdMatch, CoinIdSource, CoinRegistry, RegistryError};
+use super::currency::QuoteCurrency;
+use super::price_cache::PriceCache;
+use super::rate_limiter::RateLimiter;
+use super::retry::{get_json, FailureClass, FetchError, RetryError, RetryPolicy};
+use super::single_flight::SingleFlight;
+use crate::db::MySQL;
+use crate::models::{CoinSearchResponse, MarketChartResponse, PriceFetchResponse};
 use dotenv::dotenv;
 use once_cell::sync::Lazy;
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Client, Error as ReqwestError,
 };
-use std::sync::Arc;
-use std::{collections::HashMap, env};
-use tokio::sync::RwLock;
+use serde::de::DeserializeOwned;
+use sqlx::Error as SqlxError;
+use std::sync::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+};
+use tokio::sync::OnceCell;
+
+// [unix seconds, USD price] points of a market chart
+pub type ChartPoints = Vec<(i64, f64)>;
+
+// Every CoinGecko request in the process draws from this budget, retries included. It does not
+// depend on the client, so its metrics are available even before the first request.
+pub static COINGECKO_RATE_LIMITER: Lazy<RateLimiter> =
+    Lazy::new(|| RateLimiter::from_env("COINGECKO"));
+
+async fn limited_get_json<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+) -> Result<T, FetchError> {
+    COINGECKO_RATE_LIMITER.acquire().await;
+    get_json::<T>(client, url).await
+}
 
+#[derive(Debug)]
+pub enum CoinGeckoError {
+    MissingEnv(&'static str),
+    InvalidApiKey,
+    Client(ReqwestError),
+    Registry(RegistryError),
+}
+
+impl fmt::Display for CoinGeckoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinGeckoError::MissingEnv(name) => write!(f, "{} must be set", name),
+            CoinGeckoError::InvalidApiKey => write!(f, "Invalid CoinGecko API key header"),
+            CoinGeckoError::Client(err) => write!(f, "Error building CoinGecko client: {}", err),
+            CoinGeckoError::Registry(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// Every method takes &self, so one client is shared by all tasks without an outer lock. Shared
+// state sits behind short-lived locks that are never held across a request, and concurrent
+// requests for the same coin and day or the same asset are collapsed into one.
 pub struct CoinGecko {
     client: Client,
     base_url: String,
-    coin_id: HashMap<String, String>,
+    registry: RwLock<CoinRegistry>,
+    // Ids found through /search, keyed by normalized asset
+    coin_id: Mutex<HashMap<String, String>>,
+    retry: RetryPolicy,
+    price_cache: PriceCache,
+    store: Option<MySQL>,
+    mappings_loaded: OnceCell<()>,
+    // Normalized assets whose mapping is already in coin_id_mappings
+    stored_mappings: Mutex<HashSet<String>>,
+    price_flights: SingleFlight<(String, String), Result<f64, RetryError>>,
+    chart_flights: SingleFlight<(String, i64, i64), Result<ChartPoints, RetryError>>,
+    resolve_flights: SingleFlight<String, Result<Option<CoinIdMatch>, RetryError>>,
 }
 
 impl CoinGecko {
-    pub fn init() -> Result<Self, ReqwestError> {
+    // Prices and coin mappings are kept in the store's pool when one is given
+    pub fn init(store: Option<MySQL>) -> Result<Self, CoinGeckoError> {
         dotenv().ok();
 
-        let coingecko_base_url = env::var("COINGECKO_BASE_URL").expect("Coingecko BASE URL needed");
-        let coingecko_api_key = env::var("COINGECKO_API_KEY").expect("Coingecko API KEY needed");
+        let coingecko_base_url = env::var("COINGECKO_BASE_URL")
+            .map_err(|_| CoinGeckoError::MissingEnv("COINGECKO_BASE_URL"))?;
+        let coingecko_api_key = env::var("COINGECKO_API_KEY")
+            .map_err(|_| CoinGeckoError::MissingEnv("COINGECKO_API_KEY"))?;
 
         let mut headers = HeaderMap::new();
         headers.insert(
             "x-cg-demo-api-key",
-            HeaderValue::from_str(&coingecko_api_key).expect("Invalid Header"),
+            HeaderValue::from_str(&coingecko_api_key).map_err(|_| CoinGeckoError::InvalidApiKey)?,
         );
         headers.insert("Accept", HeaderValue::from_static("application/json"));
 
-        let client = Client::builder().default_headers(headers).build()?;
-        let coin_id = HashMap::new();
+        let client = Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(CoinGeckoError::Client)?;
+        let registry = CoinRegistry::from_env().map_err(CoinGeckoError::Registry)?;
 
         Ok(Self {
             client,
             base_url: coingecko_base_url,
-            coin_id,
+            registry: RwLock::new(registry),
+            coin_id: Mutex::new(HashMap::new()),
+            retry: RetryPolicy::from_env("COINGECKO"),
+            price_cache: PriceCache::from_env(store.clone()),
+            store,
+            mappings_loaded: OnceCell::new(),
+            stored_mappings: Mutex::new(HashSet::new()),
+            price_flights: SingleFlight::default(),
+            chart_flights: SingleFlight::default(),
+            resolve_flights: SingleFlight::default(),
         })
     }
 
-    // Fetch the USD price for a specific coin and date
-    pub async fn fetch_usd_price(&self, coin_id: &str, date: &str) -> Result<f64, ReqwestError> {
-        let url = format!("{}/coins/{}/history?date={}", self.base_url, coin_id, date);
+    // Fetch the USD price for a specific coin and date, each pair is only requested once
+    pub async fn fetch_usd_price(&self, coin_id: &str, date: &str) -> Result<f64, RetryError> {
+        if let Some(price) = self.price_cache.get(coin_id, date).await {
+            return Ok(price);
+        }
+
+        let key = (coin_id.to_string(), date.to_string());
+        self.price_flights
+            .run(key, || async {
+                // A caller that just finished the same request has filled the cache
+                if let Some(price) = self.price_cache.get(coin_id, date).await {
+                    return Ok(price);
+                }
+
+                let url = format!("{}/coins/{}/history?date={}", self.base_url, coin_id, date);
+
+                let resp: PriceFetchResponse = self
+                    .retry
+                    .run("CoinGecko price fetch", || {
+                        limited_get_json::<PriceFetchResponse>(&self.client, &url)
+                    })
+                    .await?;
+
+                let price = resp.market_data.current_price.usd;
+                self.price_cache.put(coin_id, date, price).await;
+                Ok(price)
+            })
+            .await
+    }
+
+    // USD per unit of a fiat currency on a day, from bitcoin's price in both. Rates are cached
+    // with the daily prices under a fiat: prefixed id.
+    pub async fn fetch_fiat_usd_rate(
+        &self,
+        currency: QuoteCurrency,
+        date: &str,
+    ) -> Result<f64, RetryError> {
+        let rate_id = format!("fiat:{}", currency.as_str());
+        if let Some(rate) = self.price_cache.get(&rate_id, date).await {
+            return Ok(rate);
+        }
+
+        let key = (rate_id.clone(), date.to_string());
+        self.price_flights
+            .run(key, || async {
+                if let Some(rate) = self.price_cache.get(&rate_id, date).await {
+                    return Ok(rate);
+                }
+
+                let url = format!("{}/coins/bitcoin/history?date={}", self.base_url, date);
+                let resp: PriceFetchResponse = self
+                    .retry
+                    .run("CoinGecko fiat rate fetch", || {
+                        limited_get_json::<PriceFetchResponse>(&self.client, &url)
+                    })
+                    .await?;
+
+                let prices = resp.market_data.current_price;
+                let fiat_price = match currency {
+                    QuoteCurrency::Eur => prices.eur,
+                    QuoteCurrency::Gbp => prices.gbp,
+                    _ => None,
+                };
+                let Some(fiat_price) = fiat_price.filter(|price| *price > 0.0) else {
+                    return Err(RetryError {
+                        class: FailureClass::Decode,
+                        attempts: 1,
+                        last_error: FetchError {
+                            class: FailureClass::Decode,
+                            retry_after: None,
+                            message: format!("No bitcoin {} price on {}", currency.as_str(), date),
+                        },
+                    });
+                };
+
+                let rate = prices.usd / fiat_price;
+                self.price_cache.put("bitcoin", date, prices.usd).await;
+                self.price_cache.put(&rate_id, date, rate).await;
+                Ok(rate)
+            })
+            .await
+    }
+
+    // USD prices between two unix timestamps. CoinGecko picks the granularity from the span and
+    // its age: 5 minute points only for a range within the last day, hourly for up to 90 days,
+    // daily beyond that.
+    pub async fn fetch_market_chart_range(
+        &self,
+        coin_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<ChartPoints, RetryError> {
+        let key = (coin_id.to_string(), from, to);
+        self.chart_flights
+            .run(key, || async {
+                let url = format!(
+                    "{}/coins/{}/market_chart/range?vs_currency=usd&from={}&to={}",
+                    self.base_url, coin_id, from, to
+                );
 
-        let response = self.client.get(&url).send().await?.error_for_status()?;
-        let resp: PriceFetchResponse = response.json().await?;
+                let resp: MarketChartResponse = self
+                    .retry
+                    .run("CoinGecko market chart fetch", || {
+                        limited_get_json::<MarketChartResponse>(&self.client, &url)
+                    })
+                    .await?;
 
-        Ok(resp.market_data.current_price.usd)
+                Ok(resp
+                    .prices
+                    .into_iter()
+                    .map(|(millis, price)| ((millis / 1000.0) as i64, price))
+                    .collect())
+            })
+            .await
     }
 
     // Search for a coin by name
-    pub async fn search_coin(&self, coin_name: &str) -> Result<Option<String>, ReqwestError> {
+    pub async fn search_coin(&self, coin_name: &str) -> Result<Option<String>, RetryError> {
         let url = format!("{}/search?query={}", self.base_url, coin_name);
 
-        let response = self.client.get(&url).send().await?.error_for_status()?;
+        let resp: CoinSearchResponse = self
+            .retry
+            .run("CoinGecko search", || {
+                limited_get_json::<CoinSearchResponse>(&self.client, &url)
+            })
+            .await?;
 
-        let resp: CoinSearchResponse = response.json().await?;
-
-        Ok(match resp.coins.get(0) {
-            Some(coin) => Some(coin.id.clone()),
-            None => None,
-        })
+        Ok(resp.coins.first().map(|coin| coin.id.clone()))
     }
 
-    pub fn get_coin_id(&self, asset_name: &str) -> Option<String> {
-        self.coin_id.get(asset_name).cloned()
+    // Registry entries first. Assets missing from it fall back to searching their ticker, and
+    // that guess is stored so it is only searched once, also across restarts.
+    pub async fn resolve_coin_id(&self, asset: &str) -> Result<Option<CoinIdMatch>, RetryError> {
+        if let Err(err) = self.load_mappings().await {
+            println!("Error loading coin id mappings: {}", err);
+        }
+        // Mappings are stored and looked up under one spelling of each asset
+        let asset = CoinRegistry::normalize(asset);
+        let asset = asset.as_str();
+
+        let found = self.registry.read().unwrap().lookup(asset);
+        if let Some(found) = found {
+            self.persist_mapping(asset, &found).await;
+            return Ok(Some(found));
+        }
+        if let Some(found) = self.searched_coin_id(asset) {
+            return Ok(Some(found));
+        }
+
+        self.resolve_flights
+            .run(asset.to_string(), || async {
+                if let Some(found) = self.searched_coin_id(asset) {
+                    return Ok(Some(found));
+                }
+                let Ok(Asset { ticker, .. }) = Asset::parse(asset) else {
+                    return Ok(None);
+                };
+                let Some(coin_id) = self.search_coin(&ticker).await? else {
+                    return Ok(None);
+                };
+                println!(
+                    "Low-confidence CoinGecko id {} for {}: not in the registry, matched by search",
+                    coin_id, asset
+                );
+                self.coin_id
+                    .lock()
+                    .unwrap()
+                    .insert(asset.to_string(), coin_id.clone());
+                let found = CoinIdMatch {
+                    coin_id,
+                    source: CoinIdSource::Search,
+                };
+                self.persist_mapping(asset, &found).await;
+                Ok(Some(found))
+            })
+            .await
     }
 
-    pub fn add_coin_id(&mut self, coin_name: &str, coin_id: &str) {
+    fn searched_coin_id(&self, asset: &str) -> Option<CoinIdMatch> {
         self.coin_id
-            .insert(coin_name.to_string(), coin_id.to_string());
+            .lock()
+            .unwrap()
+            .get(asset)
+            .map(|coin_id| CoinIdMatch {
+                coin_id: coin_id.clone(),
+                source: CoinIdSource::Search,
+            })
+    }
+
+    // Restores overrides and earlier search results from coin_id_mappings, once per process.
+    // A failed load is retried by the next lookup.
+    async fn load_mappings(&self) -> Result<(), SqlxError> {
+        let Some(store) = self.store.as_ref() else {
+            return Ok(());
+        };
+        self.mappings_loaded
+            .get_or_try_init(|| async {
+                let mappings = store.fetch_coin_mappings().await?;
+                println!("Loaded {} coin id mappings", mappings.len());
+
+                let mut registry = self.registry.write().unwrap();
+                let mut coin_id = self.coin_id.lock().unwrap();
+                let mut stored = self.stored_mappings.lock().unwrap();
+                for mapping in mappings {
+                    let asset = CoinRegistry::normalize(&mapping.asset);
+                    match CoinIdSource::parse(&mapping.source) {
+                        Some(CoinIdSource::Override) => {
+                            registry.set_override(&asset, &mapping.coin_id)
+                        }
+                        Some(CoinIdSource::Search) => {
+                            coin_id.insert(asset.clone(), mapping.coin_id.clone());
+                        }
+                        _ => (),
+                    }
+                    stored.insert(asset);
+                }
+                Ok(())
+            })
+            .await
+            .map(|_| ())
     }
-}
 
-pub static COINGECKO_INSTANCE: Lazy<Arc<RwLock<CoinGecko>>> = Lazy::new(|| {
-    Arc::new(RwLock::new(
-        CoinGecko::init().expect("Failed to initialize CoinGecko client"),
-    ))
-});
+    // Takes a normalized asset
+    async fn persist_mapping(&self, asset: &str, found: &CoinIdMatch) {
+        let Some(store) = self.store.as_ref() else {
+            return;
+        };
+        if self.stored_mappings.lock().unwrap().contains(asset) {
+            return;
+        }
+        match store
+            .record_coin_mapping(asset, &found.coin_id, found.source.as_str())
+            .await
+        {
+            Ok(()) => {
+                self.stored_mappings
+                    .lock()
+                    .unwrap()
+                    .insert(asset.to_string());
+            }
+            Err(err) => println!("Error saving coin id mapping for {}: {}", asset, err),
+        }
+    }
+
+    // Admin edit, takes effect immediately and wins over the curated registry
+    pub fn set_override(&self, asset: &str, coin_id: &str) {
+        self.registry.write().unwrap().set_override(asset, coin_id);
+        self.stored_mappings
+            .lock()
+            .unwrap()
+            .insert(CoinRegistry::normalize(asset));
+    }
+}
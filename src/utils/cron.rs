@@ -1,13 +1,99 @@
-use crate::{db::MySQL, fetcher::fetch_latest_data};
+use crate::{
+    db::MySQL,
+    fetcher::{fetch_latest_data, reconcile_pending_swaps, reprice_swaps},
+    utils::{
+        midgard::MidGard,
+        rate_limiter::{with_priority, Priority},
+        retry::RetryPolicy,
+        transaction_handler::TransactionHandler,
+    },
+};
+use dotenv::dotenv;
+use std::{env, sync::Arc, time::Duration};
 
-pub async fn start_cronjob(mysql: MySQL) {
+pub async fn start_cronjob(mysql: MySQL, midgard: MidGard, handler: Arc<TransactionHandler>) {
     let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1800));
     println!("Starting Cronn");
     loop {
         interval.tick().await;
         println!("Fetching Latest Data");
-        if let Err(e) = fetch_latest_data(&mysql).await {
+        // Keeping up with new swaps goes ahead of the historical backfill
+        if let Err(e) = with_priority(
+            Priority::High,
+            fetch_latest_data(&mysql, &midgard, &handler),
+        )
+        .await
+        {
             println!("Error pulling latest data: {}", e);
         }
     }
 }
+
+pub async fn start_reconciliation_job(
+    mysql: MySQL,
+    midgard: MidGard,
+    handler: Arc<TransactionHandler>,
+) {
+    dotenv().ok();
+    let every_secs = env::var("PENDING_RECONCILE_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(300);
+    let batch_size = env::var("PENDING_RECONCILE_BATCH_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<u64>().ok())
+        .unwrap_or(50);
+    // A tx still pending after this many checks is no longer polled, 288 is a day at the
+    // default interval
+    let max_attempts = env::var("PENDING_RECONCILE_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|attempts| attempts.parse::<u32>().ok())
+        .unwrap_or(288);
+
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(every_secs));
+    println!("Starting pending swap reconciliation");
+    loop {
+        interval.tick().await;
+        if let Err(e) = with_priority(
+            Priority::High,
+            reconcile_pending_swaps(&mysql, &midgard, &handler, batch_size, max_attempts),
+        )
+        .await
+        {
+            println!("Error reconciling pending swaps: {}", e);
+        }
+    }
+}
+
+// Fills in USD values for swaps stored without them. Reads REPRICE_INTERVAL_SECS,
+// REPRICE_BATCH_SIZE and the REPRICE_RETRY_* backoff settings; the backoff is measured across
+// runs, so its defaults are far longer than a single request's.
+pub async fn start_repricing_job(mysql: MySQL, midgard: MidGard, handler: Arc<TransactionHandler>) {
+    dotenv().ok();
+    let every_secs = env::var("REPRICE_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(600);
+    let batch_size = env::var("REPRICE_BATCH_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<u64>().ok())
+        .unwrap_or(50);
+    let policy = RetryPolicy::from_env_or(
+        "REPRICE",
+        RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(600),
+            max_delay: Duration::from_secs(86400),
+            jitter: 0.2,
+        },
+    );
+
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(every_secs));
+    println!("Starting swap repricing");
+    loop {
+        interval.tick().await;
+        if let Err(e) = reprice_swaps(&mysql, &midgard, &handler, batch_size, &policy).await {
+            println!("Error repricing swaps: {}", e);
+        }
+    }
+}
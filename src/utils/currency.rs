@@ -0,0 +1,83 @@
+use crate::models::actions_model::RUNE_ASSET;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuoteCurrency {
+    Usd,
+    Eur,
+    Gbp,
+    Btc,
+    Rune,
+}
+
+#[derive(Debug)]
+pub struct UnknownCurrency(pub String);
+
+impl fmt::Display for UnknownCurrency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown quote currency: {}", self.0)
+    }
+}
+
+impl QuoteCurrency {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "usd" => Some(QuoteCurrency::Usd),
+            "eur" => Some(QuoteCurrency::Eur),
+            "gbp" => Some(QuoteCurrency::Gbp),
+            "btc" => Some(QuoteCurrency::Btc),
+            "rune" => Some(QuoteCurrency::Rune),
+            _ => None,
+        }
+    }
+
+    // Comma separated currency codes, duplicates and USD (always stored) are dropped
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, UnknownCurrency> {
+        let mut currencies = Vec::new();
+        for code in value.split(',').filter(|code| !code.trim().is_empty()) {
+            let currency =
+                Self::parse(code).ok_or_else(|| UnknownCurrency(code.trim().to_string()))?;
+            if currency != QuoteCurrency::Usd && !currencies.contains(&currency) {
+                currencies.push(currency);
+            }
+        }
+        Ok(currencies)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuoteCurrency::Usd => "usd",
+            QuoteCurrency::Eur => "eur",
+            QuoteCurrency::Gbp => "gbp",
+            QuoteCurrency::Btc => "btc",
+            QuoteCurrency::Rune => "rune",
+        }
+    }
+
+    // Pool asset whose USD price is this currency's rate, for the crypto currencies
+    pub fn pool_asset(&self) -> Option<&'static str> {
+        match self {
+            QuoteCurrency::Btc => Some("BTC.BTC"),
+            QuoteCurrency::Rune => Some(RUNE_ASSET),
+            _ => None,
+        }
+    }
+
+    fn decimals(&self) -> u32 {
+        match self {
+            QuoteCurrency::Usd | QuoteCurrency::Eur | QuoteCurrency::Gbp => 2,
+            QuoteCurrency::Btc | QuoteCurrency::Rune => 8,
+        }
+    }
+
+    // Converts a USD amount given the USD price of one unit of this currency, rounded to the
+    // currency's smallest unit
+    pub fn convert_usd(&self, amount_usd: Decimal, usd_rate: Decimal) -> Option<Decimal> {
+        if usd_rate <= Decimal::ZERO {
+            return None;
+        }
+        let amount = amount_usd.checked_div(usd_rate)?;
+        Some(amount.round_dp_with_strategy(self.decimals(), RoundingStrategy::MidpointAwayFromZero))
+    }
+}
@@ -1,80 +1,408 @@
+use super::actions_source::ActionsSource;
+use super::retry::{get_json, FailureClass, FetchError, RetryError, RetryPolicy};
 use crate::models::actions_model::ActionsFetchResponse;
+use crate::models::history_model::{DepthHistoryResponse, RunePriceHistoryResponse};
+use dotenv::dotenv;
 use reqwest::Client;
-use std::time::Duration;
+use serde::de::DeserializeOwned;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fmt};
 
-pub struct MidGard;
+const DEFAULT_BASE_URL: &str = "https://vanaheimex.com";
+// Refunded swaps are reported as separate refund actions, refunds of other txs are dropped
+// when the page is parsed
+const DEFAULT_ACTION_TYPE: &str = "swap,refund";
+// Every asset kind is fetched, which kinds get stored is decided by SWAP_ASSET_KINDS
+const DEFAULT_ASSET_FILTER: &str = "";
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+// Weight given to the newest sample in the latency / error-rate moving averages
+const HEALTH_EWMA_ALPHA: f64 = 0.3;
+// Time after which the failure part of an endpoint's score has halved, so an endpoint that
+// stopped failing moves back up and gets tried again
+const HEALTH_FAILURE_HALF_LIFE: Duration = Duration::from_secs(60);
+
+// Bucket size of Midgard's /history endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryInterval {
+    FiveMinute,
+    Hour,
+    Day,
+}
+
+impl HistoryInterval {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "5min" => Some(HistoryInterval::FiveMinute),
+            "hour" => Some(HistoryInterval::Hour),
+            "day" => Some(HistoryInterval::Day),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryInterval::FiveMinute => "5min",
+            HistoryInterval::Hour => "hour",
+            HistoryInterval::Day => "day",
+        }
+    }
+
+    pub fn secs(&self) -> i64 {
+        match self {
+            HistoryInterval::FiveMinute => 300,
+            HistoryInterval::Hour => 3600,
+            HistoryInterval::Day => 86400,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MidGardConfig {
+    // Ordered by preference; the first entry wins while all endpoints are equally healthy
+    pub base_urls: Vec<String>,
+    pub action_type: String,
+    pub asset: String,
+    pub timeout: Duration,
+    pub retry: RetryPolicy,
+}
+
+impl Default for MidGardConfig {
+    fn default() -> Self {
+        Self {
+            base_urls: vec![DEFAULT_BASE_URL.to_string()],
+            action_type: DEFAULT_ACTION_TYPE.to_string(),
+            asset: DEFAULT_ASSET_FILTER.to_string(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl MidGardConfig {
+    // Reads MIDGARD_* variables, falling back to the public vanaheimex.com setup.
+    // MIDGARD_BASE_URLS takes a comma separated list, MIDGARD_BASE_URL a single endpoint.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let defaults = Self::default();
+
+        let timeout = env::var("MIDGARD_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.timeout);
+
+        let base_urls: Vec<String> = env::var("MIDGARD_BASE_URLS")
+            .or_else(|_| env::var("MIDGARD_BASE_URL"))
+            .map(|urls| {
+                urls.split(',')
+                    .map(|url| url.trim().trim_end_matches('/').to_string())
+                    .filter(|url| !url.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            base_urls: if base_urls.is_empty() {
+                defaults.base_urls
+            } else {
+                base_urls
+            },
+            action_type: env::var("MIDGARD_ACTION_TYPE").unwrap_or(defaults.action_type),
+            asset: env::var("MIDGARD_ASSET_FILTER").unwrap_or(defaults.asset),
+            timeout,
+            retry: RetryPolicy::from_env("MIDGARD"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MidGardError {
+    NoEndpoints,
+    Client(reqwest::Error),
+}
+
+impl fmt::Display for MidGardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidGardError::NoEndpoints => write!(f, "No Midgard endpoints configured"),
+            MidGardError::Client(err) => write!(f, "Error building Midgard client: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EndpointHealth {
+    pub latency_ms: f64,
+    pub error_rate: f64,
+    pub consecutive_failures: u32,
+    pub requests: u64,
+    pub last_failure: Option<Instant>,
+}
+
+impl EndpointHealth {
+    pub fn record_success(&mut self, latency: Duration) {
+        self.record(latency, 0.0);
+        self.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&mut self, latency: Duration) {
+        self.record(latency, 1.0);
+        self.consecutive_failures += 1;
+        self.last_failure = Some(Instant::now());
+    }
+
+    fn record(&mut self, latency: Duration, error: f64) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        if self.requests == 0 {
+            self.latency_ms = latency_ms;
+            self.error_rate = error;
+        } else {
+            self.latency_ms += HEALTH_EWMA_ALPHA * (latency_ms - self.latency_ms);
+            self.error_rate += HEALTH_EWMA_ALPHA * (error - self.error_rate);
+        }
+        self.requests += 1;
+    }
+
+    pub fn score(&self) -> f64 {
+        self.score_at(Instant::now())
+    }
+
+    // Lower is healthier. Errors dominate latency so a fast but failing node loses to a slow
+    // working one, and every consecutive failure pushes the endpoint further down the list.
+    // The error part fades with the time since the last failure, as a failing endpoint is only
+    // asked once the healthier ones fail and would otherwise never get the chance to recover.
+    pub fn score_at(&self, now: Instant) -> f64 {
+        let decay = self.last_failure.map_or(1.0, |failed_at| {
+            let elapsed = now.saturating_duration_since(failed_at);
+            0.5_f64.powf(elapsed.as_secs_f64() / HEALTH_FAILURE_HALF_LIFE.as_secs_f64())
+        });
+        (self.latency_ms + 100.0) * (1.0 + 10.0 * self.error_rate * decay)
+            + 1000.0 * self.consecutive_failures as f64 * decay
+    }
+}
+
+struct Endpoint {
+    base_url: String,
+    health: Mutex<EndpointHealth>,
+}
+
+#[derive(Clone)]
+pub struct MidGard {
+    client: Client,
+    config: MidGardConfig,
+    // Shared between clones so every job routes on the same health data
+    endpoints: Arc<Vec<Endpoint>>,
+}
 
 impl MidGard {
-    async fn fetch_with_retry(
-        url: &str,
-        client: &Client,
-    ) -> Result<ActionsFetchResponse, reqwest::Error> {
-        let mut attempts = 0;
-        let max_attempts = 3;
-
-        loop {
-            attempts += 1;
-            println!("Fetching URL (Attempt {}): {}", attempts, url);
-            let response = client.get(url).send().await;
-
-            match response {
-                Ok(resp) => {
-                    let parsed_response = resp.json::<ActionsFetchResponse>().await;
-                    match parsed_response {
-                        Ok(data) => return Ok(data),
-                        Err(e) => {
-                            println!("Failed to parse response: {:?}", e);
-                            if attempts >= max_attempts {
-                                return Err(e);
-                            }
-                        }
-                    }
-                }
+    pub fn init() -> Result<Self, MidGardError> {
+        Self::new(MidGardConfig::from_env())
+    }
+
+    pub fn new(config: MidGardConfig) -> Result<Self, MidGardError> {
+        if config.base_urls.is_empty() {
+            return Err(MidGardError::NoEndpoints);
+        }
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(MidGardError::Client)?;
+        let endpoints = config
+            .base_urls
+            .iter()
+            .map(|base_url| Endpoint {
+                base_url: base_url.clone(),
+                health: Mutex::new(EndpointHealth::default()),
+            })
+            .collect();
+        Ok(Self {
+            client,
+            config,
+            endpoints: Arc::new(endpoints),
+        })
+    }
+
+    // Endpoint indexes from healthiest to least healthy, configured order breaking ties
+    pub(crate) fn ranked_endpoints(&self) -> Vec<usize> {
+        let scores: Vec<f64> = self
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.health.lock().unwrap().score())
+            .collect();
+        let mut ranked: Vec<usize> = (0..self.endpoints.len()).collect();
+        ranked.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+        ranked
+    }
+
+    pub(crate) fn record_result(&self, index: usize, latency: Duration, success: bool) {
+        let mut health = self.endpoints[index].health.lock().unwrap();
+        if success {
+            health.record_success(latency);
+        } else {
+            health.record_failure(latency);
+        }
+    }
+
+    // Builds the /actions path with the configured filters and an optional paging parameter.
+    // Page tokens are Midgard height/offset cursors, so the same token is valid on every
+    // endpoint indexing the same chain and can be replayed as-is after a failover.
+    pub(crate) fn actions_path(&self, page_param: Option<(&str, &str)>) -> String {
+        let mut path = format!("/actions?type={}", self.config.action_type);
+        if !self.config.asset.is_empty() {
+            path.push_str(&format!("&asset={}", self.config.asset));
+        }
+        if let Some((key, value)) = page_param {
+            if !value.is_empty() {
+                path.push_str(&format!("&{}={}", key, value));
+            }
+        }
+        path
+    }
+
+    pub async fn fetch_actions_by_tx_id(
+        &self,
+        tx_id: &str,
+    ) -> Result<ActionsFetchResponse, RetryError> {
+        // No type/asset filters: a settled swap may be reported as a refund action
+        let path = format!("/actions?txid={}", tx_id);
+        self.fetch_with_retry(&path).await
+    }
+
+    // Pool depths over the interval ending at or before the timestamp
+    pub async fn fetch_depth_history(
+        &self,
+        pool: &str,
+        interval: HistoryInterval,
+        timestamp: i64,
+    ) -> Result<DepthHistoryResponse, RetryError> {
+        let path = format!(
+            "/history/depths/{}?interval={}&count=1&to={}",
+            pool,
+            interval.as_str(),
+            timestamp
+        );
+        self.fetch_with_retry(&path).await
+    }
+
+    pub async fn fetch_rune_price_history(
+        &self,
+        interval: HistoryInterval,
+        timestamp: i64,
+    ) -> Result<RunePriceHistoryResponse, RetryError> {
+        let path = format!(
+            "/history/rune?interval={}&count=1&to={}",
+            interval.as_str(),
+            timestamp
+        );
+        self.fetch_with_retry(&path).await
+    }
+
+    async fn fetch_from_endpoint<T: DeserializeOwned>(
+        &self,
+        index: usize,
+        path: &str,
+    ) -> Result<T, FetchError> {
+        let url = format!("{}{}", self.endpoints[index].base_url, path);
+        println!("Fetching URL: {}", url);
+
+        let started = Instant::now();
+        let result = get_json::<T>(&self.client, &url).await;
+        // A 4xx is a problem with our request, not with the endpoint's health
+        let healthy = match &result {
+            Ok(_) => true,
+            Err(e) => e.class == FailureClass::ClientError,
+        };
+        self.record_result(index, started.elapsed(), healthy);
+        result
+    }
+
+    // One attempt walks the endpoints from healthiest to least healthy. A client error means
+    // the request itself is wrong, so there is no point asking the remaining endpoints.
+    async fn fetch_from_any_endpoint<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<T, FetchError> {
+        let mut last_error = None;
+        for index in self.ranked_endpoints() {
+            match self.fetch_from_endpoint(index, path).await {
+                Ok(data) => return Ok(data),
+                Err(e) if e.class == FailureClass::ClientError => return Err(e),
                 Err(e) => {
-                    println!("Request failed: {:?}", e);
-                    if attempts >= max_attempts {
-                        return Err(e);
-                    }
+                    println!(
+                        "Request to {} failed: {}",
+                        self.endpoints[index].base_url, e
+                    );
+                    last_error = Some(e);
                 }
             }
-            tokio::time::sleep(Duration::from_millis(500)).await;
         }
+        // new() rejects a client without endpoints, so at least one was asked
+        Err(last_error.expect("Midgard client has no endpoints configured"))
+    }
+
+    async fn fetch_with_retry<T: DeserializeOwned>(&self, path: &str) -> Result<T, RetryError> {
+        self.config
+            .retry
+            .run("Midgard request", || self.fetch_from_any_endpoint(path))
+            .await
     }
 
     pub async fn fetch_actions_with_nextpage(
+        &self,
         next_page_token: &str,
-    ) -> Result<ActionsFetchResponse, reqwest::Error> {
-        let client = Client::builder().timeout(Duration::from_secs(5)).build()?;
-        let url = if next_page_token.is_empty() {
-            "https://vanaheimex.com/actions?type=swap&asset=notrade".to_string()
-        } else {
-            format!(
-                "https://vanaheimex.com/actions?type=swap&asset=notrade&nextPageToken={}",
-                next_page_token
-            )
-        };
-        Self::fetch_with_retry(&url, &client).await
+    ) -> Result<ActionsFetchResponse, RetryError> {
+        let path = self.actions_path(Some(("nextPageToken", next_page_token)));
+        self.fetch_with_retry(&path).await
     }
 
     pub async fn fetch_actions_with_prevpage(
+        &self,
         prev_page_token: &str,
-    ) -> Result<ActionsFetchResponse, reqwest::Error> {
-        let client = Client::builder().timeout(Duration::from_secs(5)).build()?;
-        let url = format!(
-            "https://vanaheimex.com/actions?type=swap&asset=notrade&prevPageToken={}",
-            prev_page_token
-        );
-        Self::fetch_with_retry(&url, &client).await
+    ) -> Result<ActionsFetchResponse, RetryError> {
+        let path = self.actions_path(Some(("prevPageToken", prev_page_token)));
+        self.fetch_with_retry(&path).await
     }
 
     pub async fn fetch_actions_with_timestamp(
+        &self,
         timestamp: &str,
-    ) -> Result<ActionsFetchResponse, reqwest::Error> {
-        let client = Client::builder().timeout(Duration::from_secs(5)).build()?;
-        let url = format!(
-            "https://vanaheimex.com/actions?type=swap&asset=notrade&fromTimestamp={}",
-            timestamp
-        );
-        Self::fetch_with_retry(&url, &client).await
+    ) -> Result<ActionsFetchResponse, RetryError> {
+        let path = self.actions_path(Some(("fromTimestamp", timestamp)));
+        self.fetch_with_retry(&path).await
+    }
+}
+
+impl ActionsSource for MidGard {
+    type Error = RetryError;
+
+    fn name(&self) -> &str {
+        "midgard"
+    }
+
+    async fn fetch_next_page(
+        &self,
+        next_page_token: &str,
+    ) -> Result<ActionsFetchResponse, RetryError> {
+        self.fetch_actions_with_nextpage(next_page_token).await
+    }
+
+    async fn fetch_prev_page(
+        &self,
+        prev_page_token: &str,
+    ) -> Result<ActionsFetchResponse, RetryError> {
+        self.fetch_actions_with_prevpage(prev_page_token).await
+    }
+
+    async fn fetch_from_timestamp(
+        &self,
+        timestamp: &str,
+    ) -> Result<ActionsFetchResponse, RetryError> {
+        self.fetch_actions_with_timestamp(timestamp).await
+    }
+
+    async fn fetch_by_tx_id(&self, tx_id: &str) -> Result<ActionsFetchResponse, RetryError> {
+        self.fetch_actions_by_tx_id(tx_id).await
     }
 }
@@ -0,0 +1,74 @@
+use crate::db::MySQL;
+use crate::utils::format_date_for_sql;
+use dotenv::dotenv;
+use lru::LruCache;
+use std::env;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+const DEFAULT_PRICE_CACHE_CAPACITY: usize = 10_000;
+
+// Daily USD prices keyed by (coin id, DD-MM-YYYY date). Recently used prices are kept in
+// memory, every price is also written to the price_cache table so it survives restarts.
+pub struct PriceCache {
+    memory: Mutex<LruCache<(String, String), f64>>,
+    store: Option<MySQL>,
+}
+
+impl PriceCache {
+    pub fn new(capacity: usize, store: Option<MySQL>) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            memory: Mutex::new(LruCache::new(capacity)),
+            store,
+        }
+    }
+
+    // Reads PRICE_CACHE_CAPACITY, the number of prices held in memory
+    pub fn from_env(store: Option<MySQL>) -> Self {
+        dotenv().ok();
+        let capacity = env::var("PRICE_CACHE_CAPACITY")
+            .ok()
+            .and_then(|capacity| capacity.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_PRICE_CACHE_CAPACITY);
+        Self::new(capacity, store)
+    }
+
+    pub async fn get(&self, coin_id: &str, date: &str) -> Option<f64> {
+        let key = (coin_id.to_string(), date.to_string());
+        if let Some(price) = self.memory.lock().unwrap().get(&key) {
+            return Some(*price);
+        }
+
+        let store = self.store.as_ref()?;
+        let sql_date = format_date_for_sql(date).ok()?;
+        match store.fetch_cached_price(coin_id, &sql_date).await {
+            Ok(Some(price)) => {
+                self.memory.lock().unwrap().put(key, price);
+                Some(price)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                println!(
+                    "Error reading cached price for {} on {}: {}",
+                    coin_id, date, err
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, coin_id: &str, date: &str, price: f64) {
+        self.memory
+            .lock()
+            .unwrap()
+            .put((coin_id.to_string(), date.to_string()), price);
+
+        let (Some(store), Ok(sql_date)) = (self.store.as_ref(), format_date_for_sql(date)) else {
+            return;
+        };
+        if let Err(err) = store.store_cached_price(coin_id, &sql_date, price).await {
+            println!("Error caching price for {} on {}: {}", coin_id, date, err);
+        }
+    }
+}
@@ -0,0 +1,773 @@
+use super::asset::Asset;
+use super::coin_registry::Confidence;
+use super::coingecko::CoinGecko;
+use super::midgard::{HistoryInterval, MidGard};
+use crate::models::actions_model::{SwapTransaction, RUNE_ASSET};
+use crate::utils::parse_f64;
+use chrono::{TimeZone, Utc};
+use dotenv::dotenv;
+use lru::LruCache;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::{env, fmt, fs};
+
+// A static file price older than this is not used for a swap
+const DEFAULT_PRICE_FILE_MAX_AGE_SECS: i64 = 86400;
+// Number of (coin, window) market chart series kept in memory
+const CHART_SERIES_CAPACITY: usize = 256;
+// Number of depth and RUNE price history intervals kept in memory
+const DEPTH_HISTORY_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceQuote {
+    pub asset: String,
+    pub price_usd: f64,
+    pub provider: &'static str,
+    // CoinGecko id the price was looked up by, for CoinGecko providers
+    pub coin_id: Option<String>,
+    // When the price was observed, which can differ from the requested timestamp
+    pub timestamp: i64,
+    pub confidence: Confidence,
+}
+
+#[derive(Debug)]
+pub enum OracleError {
+    NoPrice(String),
+    Unsupported(String),
+    Fetch(String),
+    Config(String),
+}
+
+impl fmt::Display for OracleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OracleError::NoPrice(asset) => write!(f, "No price available for: {}", asset),
+            OracleError::Unsupported(asset) => write!(f, "Asset not supported: {}", asset),
+            OracleError::Fetch(err) => write!(f, "Price fetch error: {}", err),
+            OracleError::Config(err) => write!(f, "Price oracle configuration error: {}", err),
+        }
+    }
+}
+
+// A source of USD prices for pool assets (e.g. "BTC.BTC") at a unix timestamp in seconds
+pub trait PriceOracle {
+    fn name(&self) -> &'static str;
+
+    fn price_usd(
+        &self,
+        asset: &str,
+        timestamp: i64,
+    ) -> impl Future<Output = Result<PriceQuote, OracleError>> + Send;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderKind {
+    CoinGecko,
+    CoinGeckoIntraday,
+    MidgardMetadata,
+    PoolDepth,
+    StaticFile,
+}
+
+impl ProviderKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "coingecko" => Some(ProviderKind::CoinGecko),
+            "coingecko_intraday" => Some(ProviderKind::CoinGeckoIntraday),
+            "midgard" => Some(ProviderKind::MidgardMetadata),
+            "pool_depth" => Some(ProviderKind::PoolDepth),
+            "file" => Some(ProviderKind::StaticFile),
+            _ => None,
+        }
+    }
+
+    // Comma separated provider names in the order they should be tried
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, OracleError> {
+        value
+            .split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| {
+                Self::parse(name)
+                    .ok_or_else(|| OracleError::Config(format!("Unknown price provider: {}", name)))
+            })
+            .collect()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::CoinGecko => "coingecko",
+            ProviderKind::CoinGeckoIntraday => "coingecko_intraday",
+            ProviderKind::MidgardMetadata => "midgard",
+            ProviderKind::PoolDepth => "pool_depth",
+            ProviderKind::StaticFile => "file",
+        }
+    }
+
+    pub fn needs_coingecko(&self) -> bool {
+        matches!(
+            self,
+            ProviderKind::CoinGecko | ProviderKind::CoinGeckoIntraday
+        )
+    }
+}
+
+// CoinGecko daily history, priced at 00:00 UTC of the swap's day
+pub struct CoinGeckoOracle {
+    coingecko: Arc<CoinGecko>,
+}
+
+impl CoinGeckoOracle {
+    pub fn new(coingecko: Arc<CoinGecko>) -> Self {
+        Self { coingecko }
+    }
+}
+
+impl PriceOracle for CoinGeckoOracle {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    async fn price_usd(&self, asset: &str, timestamp: i64) -> Result<PriceQuote, OracleError> {
+        let day = Utc
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .ok_or_else(|| OracleError::Fetch(format!("Invalid timestamp: {}", timestamp)))?
+            .date_naive();
+        let date = day.format("%d-%m-%Y").to_string();
+
+        let coingecko = &self.coingecko;
+
+        let coin = coingecko
+            .resolve_coin_id(asset)
+            .await
+            .map_err(|err| {
+                println!("Error resolving coin ID for asset: {} ({})", asset, err);
+                OracleError::Fetch(err.to_string())
+            })?
+            .ok_or_else(|| {
+                println!("Coin ID not found for asset: {}", asset);
+                OracleError::Unsupported(asset.to_string())
+            })?;
+        let coin_id = coin.coin_id.clone();
+        let price_usd = coingecko
+            .fetch_usd_price(coin_id.as_str(), &date)
+            .await
+            .map_err(|err| {
+                println!(
+                    "Error fetching price for coin ID: {} on date: {} ({})",
+                    coin_id, date, err
+                );
+                OracleError::Fetch(err.to_string())
+            })?;
+
+        Ok(PriceQuote {
+            asset: asset.to_string(),
+            price_usd,
+            provider: self.name(),
+            coin_id: Some(coin.coin_id.clone()),
+            timestamp: day.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp(),
+            confidence: coin.confidence(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartResolution {
+    FiveMinute,
+    Hourly,
+}
+
+impl ChartResolution {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "5min" => Some(ChartResolution::FiveMinute),
+            "hourly" => Some(ChartResolution::Hourly),
+            _ => None,
+        }
+    }
+
+    // Span fetched per request, chosen so CoinGecko answers with this resolution
+    pub fn window_secs(&self) -> i64 {
+        match self {
+            ChartResolution::FiveMinute => 86400,
+            ChartResolution::Hourly => 30 * 86400,
+        }
+    }
+
+    pub fn step_secs(&self) -> i64 {
+        match self {
+            ChartResolution::FiveMinute => 300,
+            ChartResolution::Hourly => 3600,
+        }
+    }
+
+    // CoinGecko only returns 5 minute points for a range starting within the last day, an
+    // older day comes back hourly. Such lookups use hourly windows instead.
+    pub fn at(&self, timestamp: i64, now: i64) -> Self {
+        let start = timestamp - timestamp.rem_euclid(self.window_secs());
+        match self {
+            ChartResolution::FiveMinute if start < now - 86400 => ChartResolution::Hourly,
+            resolution => *resolution,
+        }
+    }
+
+    // Widest spacing between points that is still interpolated across
+    pub fn max_gap(&self) -> i64 {
+        2 * self.step_secs()
+    }
+}
+
+// Linear interpolation between the chart points either side of the timestamp, returning the
+// price and the timestamp of the nearest point. Past either end of the series, or across a gap
+// wider than max_gap, only a single point within max_gap is used.
+pub fn interpolate_price(
+    points: &[(i64, f64)],
+    timestamp: i64,
+    max_gap: i64,
+) -> Option<(f64, i64)> {
+    let after = points.partition_point(|(point_timestamp, _)| *point_timestamp < timestamp);
+    let prev = after.checked_sub(1).and_then(|index| points.get(index));
+    let next = points.get(after);
+
+    match (prev, next) {
+        (_, Some(&(t, price))) if t == timestamp => Some((price, t)),
+        (Some(&(t0, p0)), Some(&(t1, p1))) if t1 - t0 <= max_gap => {
+            let weight = (timestamp - t0) as f64 / (t1 - t0) as f64;
+            let nearest = if timestamp - t0 <= t1 - timestamp {
+                t0
+            } else {
+                t1
+            };
+            Some((p0 + (p1 - p0) * weight, nearest))
+        }
+        _ => [prev, next]
+            .into_iter()
+            .flatten()
+            .filter(|(t, _)| (t - timestamp).abs() <= max_gap)
+            .min_by_key(|(t, _)| (t - timestamp).abs())
+            .map(|&(t, price)| (price, t)),
+    }
+}
+
+struct ChartSeries {
+    points: Vec<(i64, f64)>,
+    // The window had fully elapsed when fetched, so no later points can appear
+    complete: bool,
+}
+
+// CoinGecko market_chart/range series, fetched a whole window per coin at a time and
+// interpolated to the swap's timestamp
+pub struct CoinGeckoIntradayOracle {
+    coingecko: Arc<CoinGecko>,
+    resolution: ChartResolution,
+    // Keyed by coin id, window start and window length
+    series: Mutex<LruCache<(String, i64, i64), ChartSeries>>,
+}
+
+impl CoinGeckoIntradayOracle {
+    pub fn new(coingecko: Arc<CoinGecko>, resolution: ChartResolution) -> Self {
+        Self {
+            coingecko,
+            resolution,
+            series: Mutex::new(LruCache::new(
+                NonZeroUsize::new(CHART_SERIES_CAPACITY).unwrap(),
+            )),
+        }
+    }
+
+    // Reads COINGECKO_CHART_RESOLUTION: 5min | hourly (default)
+    pub fn from_env(coingecko: Arc<CoinGecko>) -> Self {
+        dotenv().ok();
+        let resolution = env::var("COINGECKO_CHART_RESOLUTION")
+            .ok()
+            .and_then(|resolution| ChartResolution::parse(&resolution))
+            .unwrap_or(ChartResolution::Hourly);
+        Self::new(coingecko, resolution)
+    }
+
+    fn cached_price(
+        &self,
+        key: &(String, i64, i64),
+        timestamp: i64,
+        max_gap: i64,
+    ) -> Option<Option<(f64, i64)>> {
+        let mut series = self.series.lock().unwrap();
+        let cached = series.get(key)?;
+        let covers = cached.complete
+            || cached
+                .points
+                .last()
+                .is_some_and(|(last_timestamp, _)| *last_timestamp >= timestamp);
+        if !covers {
+            return None;
+        }
+        Some(interpolate_price(&cached.points, timestamp, max_gap))
+    }
+}
+
+impl PriceOracle for CoinGeckoIntradayOracle {
+    fn name(&self) -> &'static str {
+        "coingecko_intraday"
+    }
+
+    async fn price_usd(&self, asset: &str, timestamp: i64) -> Result<PriceQuote, OracleError> {
+        let resolution = self.resolution.at(timestamp, Utc::now().timestamp());
+        let window = resolution.window_secs();
+        let start = timestamp - timestamp.rem_euclid(window);
+        let end = start + window;
+
+        let coingecko = &self.coingecko;
+
+        let coin = coingecko
+            .resolve_coin_id(asset)
+            .await
+            .map_err(|err| {
+                println!("Error resolving coin ID for asset: {} ({})", asset, err);
+                OracleError::Fetch(err.to_string())
+            })?
+            .ok_or_else(|| {
+                println!("Coin ID not found for asset: {}", asset);
+                OracleError::Unsupported(asset.to_string())
+            })?;
+        let key = (coin.coin_id.clone(), start, window);
+
+        let price = match self.cached_price(&key, timestamp, resolution.max_gap()) {
+            Some(price) => price,
+            None => {
+                let points = coingecko
+                    .fetch_market_chart_range(&coin.coin_id, start, end)
+                    .await
+                    .map_err(|err| {
+                        println!(
+                            "Error fetching market chart for coin ID: {} from {} to {} ({})",
+                            coin.coin_id, start, end, err
+                        );
+                        OracleError::Fetch(err.to_string())
+                    })?;
+                let price = interpolate_price(&points, timestamp, resolution.max_gap());
+                let complete = end <= Utc::now().timestamp();
+                self.series
+                    .lock()
+                    .unwrap()
+                    .put(key, ChartSeries { points, complete });
+                price
+            }
+        };
+        let (price_usd, price_timestamp) =
+            price.ok_or_else(|| OracleError::NoPrice(asset.to_string()))?;
+
+        Ok(PriceQuote {
+            asset: asset.to_string(),
+            price_usd,
+            provider: self.name(),
+            coin_id: Some(coin.coin_id.clone()),
+            timestamp: price_timestamp,
+            confidence: coin.confidence(),
+        })
+    }
+}
+
+// inPriceUSD/outPriceUSD that Midgard reports on each swap action. It only prices the swap it
+// is handed, so it never answers lookups made without one.
+pub struct MidgardMetadataOracle;
+
+impl MidgardMetadataOracle {
+    pub fn name(&self) -> &'static str {
+        "midgard"
+    }
+
+    pub fn quote(
+        &self,
+        swap: &SwapTransaction,
+        asset: &str,
+        timestamp: i64,
+    ) -> Result<PriceQuote, OracleError> {
+        let price = swap
+            .midgard_price_usd(asset)
+            .ok_or_else(|| OracleError::NoPrice(asset.to_string()))?;
+
+        Ok(PriceQuote {
+            asset: asset.to_string(),
+            price_usd: price,
+            provider: self.name(),
+            coin_id: None,
+            timestamp,
+            confidence: Confidence::High,
+        })
+    }
+}
+
+// USD price of one unit of a pool's asset: the pool's RUNE per asset unit, times RUNE/USD.
+// Both depths are in the same 1e8 base units, so their ratio needs no scaling.
+pub fn pool_price_usd(asset_depth: f64, rune_depth: f64, rune_price_usd: f64) -> Option<f64> {
+    if asset_depth <= 0.0 || rune_depth <= 0.0 || rune_price_usd <= 0.0 {
+        return None;
+    }
+    Some(rune_depth / asset_depth * rune_price_usd)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PoolDepths {
+    asset_depth: f64,
+    rune_depth: f64,
+    // End of the interval the depths were observed at
+    observed_at: i64,
+}
+
+// Prices derived from Midgard's pool depth and RUNE price history, so every asset with a pool
+// is priced without a third party. Each swap is priced at the end of the last interval that
+// closed before it, and intervals are cached since many swaps share them.
+pub struct PoolDepthOracle {
+    midgard: MidGard,
+    interval: HistoryInterval,
+    // RUNE/USD and the interval end it was observed at, keyed by interval start
+    rune_prices: Mutex<LruCache<i64, (f64, i64)>>,
+    // Keyed by pool and interval start
+    depths: Mutex<LruCache<(String, i64), PoolDepths>>,
+}
+
+impl PoolDepthOracle {
+    pub fn new(midgard: MidGard, interval: HistoryInterval) -> Self {
+        Self {
+            midgard,
+            interval,
+            rune_prices: Mutex::new(LruCache::new(
+                NonZeroUsize::new(DEPTH_HISTORY_CAPACITY).unwrap(),
+            )),
+            depths: Mutex::new(LruCache::new(
+                NonZeroUsize::new(DEPTH_HISTORY_CAPACITY).unwrap(),
+            )),
+        }
+    }
+
+    // Reads POOL_DEPTH_INTERVAL: 5min (default) | hour | day
+    pub fn from_env(midgard: MidGard) -> Self {
+        dotenv().ok();
+        let interval = env::var("POOL_DEPTH_INTERVAL")
+            .ok()
+            .and_then(|interval| HistoryInterval::parse(&interval))
+            .unwrap_or(HistoryInterval::FiveMinute);
+        Self::new(midgard, interval)
+    }
+
+    // Start of the interval the timestamp falls in, its predecessor ends right there
+    fn interval_start(&self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.interval.secs())
+    }
+
+    async fn rune_price(&self, timestamp: i64) -> Result<(f64, i64), OracleError> {
+        let start = self.interval_start(timestamp);
+        if let Some(cached) = self.rune_prices.lock().unwrap().get(&start) {
+            return Ok(*cached);
+        }
+
+        let history = self
+            .midgard
+            .fetch_rune_price_history(self.interval, start)
+            .await
+            .map_err(|err| OracleError::Fetch(err.to_string()))?;
+        let interval = history
+            .intervals
+            .last()
+            .ok_or_else(|| OracleError::NoPrice(RUNE_ASSET.to_string()))?;
+        let price = parse_f64(&interval.runePriceUSD)
+            .ok()
+            .filter(|price| *price > 0.0)
+            .ok_or_else(|| OracleError::NoPrice(RUNE_ASSET.to_string()))?;
+        let observed = (price, interval.endTime.parse::<i64>().unwrap_or(start));
+
+        self.rune_prices.lock().unwrap().put(start, observed);
+        Ok(observed)
+    }
+
+    async fn depths(&self, pool: &str, timestamp: i64) -> Result<PoolDepths, OracleError> {
+        let key = (pool.to_string(), self.interval_start(timestamp));
+        if let Some(cached) = self.depths.lock().unwrap().get(&key) {
+            return Ok(*cached);
+        }
+
+        let history = self
+            .midgard
+            .fetch_depth_history(pool, self.interval, key.1)
+            .await
+            .map_err(|err| OracleError::Fetch(err.to_string()))?;
+        let interval = history
+            .intervals
+            .last()
+            .ok_or_else(|| OracleError::NoPrice(pool.to_string()))?;
+        let observed = PoolDepths {
+            asset_depth: parse_f64(&interval.assetDepth).unwrap_or_default(),
+            rune_depth: parse_f64(&interval.runeDepth).unwrap_or_default(),
+            observed_at: interval.endTime.parse::<i64>().unwrap_or(key.1),
+        };
+
+        self.depths.lock().unwrap().put(key, observed);
+        Ok(observed)
+    }
+}
+
+impl PriceOracle for PoolDepthOracle {
+    fn name(&self) -> &'static str {
+        "pool_depth"
+    }
+
+    async fn price_usd(&self, asset: &str, timestamp: i64) -> Result<PriceQuote, OracleError> {
+        let (rune_price_usd, rune_observed) = self.rune_price(timestamp).await?;
+        let (price_usd, observed) = if Asset::parse(asset).is_ok_and(|asset| asset.is_rune()) {
+            (rune_price_usd, rune_observed)
+        } else {
+            let depths = self.depths(asset, timestamp).await?;
+            let price_usd = pool_price_usd(depths.asset_depth, depths.rune_depth, rune_price_usd)
+                .ok_or_else(|| OracleError::NoPrice(asset.to_string()))?;
+            (price_usd, depths.observed_at)
+        };
+
+        Ok(PriceQuote {
+            asset: asset.to_string(),
+            price_usd,
+            provider: self.name(),
+            coin_id: None,
+            timestamp: observed,
+            confidence: Confidence::High,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PriceFileEntry {
+    asset: String,
+    timestamp: i64,
+    price_usd: f64,
+}
+
+// Prices loaded from a local file for offline runs. JSON files hold an array of
+// {"asset", "timestamp", "price_usd"} objects, anything else is read as CSV with the same
+// three columns and an optional header line.
+pub struct StaticPriceOracle {
+    prices: HashMap<String, BTreeMap<i64, f64>>,
+    max_age_secs: i64,
+}
+
+impl StaticPriceOracle {
+    pub fn load(path: &str) -> Result<Self, OracleError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| OracleError::Config(format!("Error reading {}: {}", path, err)))?;
+        if !path.ends_with(".json") {
+            return Self::from_csv(&contents, DEFAULT_PRICE_FILE_MAX_AGE_SECS);
+        }
+        let entries = serde_json::from_str::<Vec<PriceFileEntry>>(&contents)
+            .map_err(|err| OracleError::Config(format!("Error parsing {}: {}", path, err)))?;
+        Ok(Self::from_entries(entries, DEFAULT_PRICE_FILE_MAX_AGE_SECS))
+    }
+
+    fn parse_csv(contents: &str) -> Result<Vec<PriceFileEntry>, OracleError> {
+        let mut entries = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("asset,") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let entry = match fields.as_slice() {
+                [asset, timestamp, price_usd] => timestamp
+                    .parse::<i64>()
+                    .ok()
+                    .zip(price_usd.parse::<f64>().ok())
+                    .map(|(timestamp, price_usd)| PriceFileEntry {
+                        asset: asset.to_string(),
+                        timestamp,
+                        price_usd,
+                    }),
+                _ => None,
+            };
+            let entry = entry.ok_or_else(|| {
+                OracleError::Config(format!("Invalid price line {}: {}", number + 1, line))
+            })?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn from_entries(entries: Vec<PriceFileEntry>, max_age_secs: i64) -> Self {
+        let mut prices: HashMap<String, BTreeMap<i64, f64>> = HashMap::new();
+        for entry in entries {
+            prices
+                .entry(entry.asset)
+                .or_default()
+                .insert(entry.timestamp, entry.price_usd);
+        }
+        Self {
+            prices,
+            max_age_secs,
+        }
+    }
+
+    pub fn from_csv(contents: &str, max_age_secs: i64) -> Result<Self, OracleError> {
+        Ok(Self::from_entries(Self::parse_csv(contents)?, max_age_secs))
+    }
+}
+
+impl PriceOracle for StaticPriceOracle {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    // The latest price at or before the timestamp, as long as it isn't too old
+    async fn price_usd(&self, asset: &str, timestamp: i64) -> Result<PriceQuote, OracleError> {
+        let (price_timestamp, price_usd) = self
+            .prices
+            .get(asset)
+            .and_then(|prices| prices.range(..=timestamp).next_back())
+            .filter(|(price_timestamp, _)| timestamp - **price_timestamp <= self.max_age_secs)
+            .ok_or_else(|| OracleError::NoPrice(asset.to_string()))?;
+
+        Ok(PriceQuote {
+            asset: asset.to_string(),
+            price_usd: *price_usd,
+            provider: self.name(),
+            coin_id: None,
+            timestamp: *price_timestamp,
+            confidence: Confidence::High,
+        })
+    }
+}
+
+pub enum PriceProvider {
+    CoinGecko(CoinGeckoOracle),
+    CoinGeckoIntraday(CoinGeckoIntradayOracle),
+    MidgardMetadata(MidgardMetadataOracle),
+    PoolDepth(Box<PoolDepthOracle>),
+    StaticFile(StaticPriceOracle),
+}
+
+impl PriceProvider {
+    // Pool depth and CoinGecko prices go through the shared clients, so their rate limits,
+    // caches and endpoint failover apply to them as well. The CoinGecko client is only built
+    // when the configuration needs it.
+    pub fn build(
+        kind: ProviderKind,
+        price_file: Option<&str>,
+        midgard: &MidGard,
+        coingecko: Option<&Arc<CoinGecko>>,
+    ) -> Result<Self, OracleError> {
+        let coingecko = || {
+            coingecko.cloned().ok_or_else(|| {
+                OracleError::Config(format!(
+                    "the {} provider needs the CoinGecko client",
+                    kind.as_str()
+                ))
+            })
+        };
+        Ok(match kind {
+            ProviderKind::CoinGecko => PriceProvider::CoinGecko(CoinGeckoOracle::new(coingecko()?)),
+            ProviderKind::CoinGeckoIntraday => {
+                PriceProvider::CoinGeckoIntraday(CoinGeckoIntradayOracle::from_env(coingecko()?))
+            }
+            ProviderKind::MidgardMetadata => PriceProvider::MidgardMetadata(MidgardMetadataOracle),
+            ProviderKind::PoolDepth => {
+                PriceProvider::PoolDepth(Box::new(PoolDepthOracle::from_env(midgard.clone())))
+            }
+            ProviderKind::StaticFile => {
+                let path = price_file.ok_or_else(|| {
+                    OracleError::Config("PRICE_FILE is required for the file provider".to_string())
+                })?;
+                PriceProvider::StaticFile(StaticPriceOracle::load(path)?)
+            }
+        })
+    }
+}
+
+impl PriceProvider {
+    // The swap being valued, when there is one, lets the metadata provider use its own prices
+    pub async fn price_for(
+        &self,
+        swap: Option<&SwapTransaction>,
+        asset: &str,
+        timestamp: i64,
+    ) -> Result<PriceQuote, OracleError> {
+        match self {
+            PriceProvider::MidgardMetadata(oracle) => match swap {
+                Some(swap) => oracle.quote(swap, asset, timestamp),
+                None => Err(OracleError::NoPrice(asset.to_string())),
+            },
+            _ => self.price_usd(asset, timestamp).await,
+        }
+    }
+}
+
+impl PriceOracle for PriceProvider {
+    fn name(&self) -> &'static str {
+        match self {
+            PriceProvider::CoinGecko(oracle) => oracle.name(),
+            PriceProvider::CoinGeckoIntraday(oracle) => oracle.name(),
+            PriceProvider::MidgardMetadata(oracle) => oracle.name(),
+            PriceProvider::PoolDepth(oracle) => oracle.name(),
+            PriceProvider::StaticFile(oracle) => oracle.name(),
+        }
+    }
+
+    async fn price_usd(&self, asset: &str, timestamp: i64) -> Result<PriceQuote, OracleError> {
+        match self {
+            PriceProvider::CoinGecko(oracle) => oracle.price_usd(asset, timestamp).await,
+            PriceProvider::CoinGeckoIntraday(oracle) => oracle.price_usd(asset, timestamp).await,
+            PriceProvider::MidgardMetadata(_) => Err(OracleError::NoPrice(asset.to_string())),
+            PriceProvider::PoolDepth(oracle) => oracle.price_usd(asset, timestamp).await,
+            PriceProvider::StaticFile(oracle) => oracle.price_usd(asset, timestamp).await,
+        }
+    }
+}
+
+// Asks each provider in turn and returns the first price found
+pub struct CompositeOracle {
+    providers: Vec<PriceProvider>,
+}
+
+impl CompositeOracle {
+    pub fn new(providers: Vec<PriceProvider>) -> Self {
+        Self { providers }
+    }
+
+    pub fn build(
+        kinds: &[ProviderKind],
+        price_file: Option<&str>,
+        midgard: &MidGard,
+        coingecko: Option<&Arc<CoinGecko>>,
+    ) -> Result<Self, OracleError> {
+        let providers = kinds
+            .iter()
+            .map(|kind| PriceProvider::build(*kind, price_file, midgard, coingecko))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(providers))
+    }
+
+    // Like price_usd, with the swap being valued offered to the providers that read prices
+    // from its metadata
+    pub async fn price_for(
+        &self,
+        swap: Option<&SwapTransaction>,
+        asset: &str,
+        timestamp: i64,
+    ) -> Result<PriceQuote, OracleError> {
+        let mut last_error = OracleError::NoPrice(asset.to_string());
+        for provider in &self.providers {
+            match provider.price_for(swap, asset, timestamp).await {
+                Ok(quote) => return Ok(quote),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl PriceOracle for CompositeOracle {
+    fn name(&self) -> &'static str {
+        "composite"
+    }
+
+    async fn price_usd(&self, asset: &str, timestamp: i64) -> Result<PriceQuote, OracleError> {
+        self.price_for(None, asset, timestamp).await
+    }
+}
@@ -0,0 +1,202 @@
+use dotenv::dotenv;
+use serde::Serialize;
+use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How long a backfill request waits before checking again while tail requests are queued
+const LOW_PRIORITY_POLL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    // Keeping up with new swaps: tail ingestion and pending reconciliation
+    High,
+    // Historical backfill, takes whatever budget the tail jobs leave
+    Low,
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+// Runs a job with the given priority for every rate limited request made inside it
+pub async fn with_priority<F: Future>(priority: Priority, job: F) -> F::Output {
+    PRIORITY.scope(priority, job).await
+}
+
+// Requests outside any prioritised job are treated as backfill
+pub fn current_priority() -> Priority {
+    PRIORITY
+        .try_with(|priority| *priority)
+        .unwrap_or(Priority::Low)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiPlan {
+    Demo,
+    Pro,
+}
+
+impl ApiPlan {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "demo" => Some(ApiPlan::Demo),
+            "pro" => Some(ApiPlan::Pro),
+            _ => None,
+        }
+    }
+
+    pub fn calls_per_minute(&self) -> u32 {
+        match self {
+            ApiPlan::Demo => 30,
+            ApiPlan::Pro => 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RateLimitMetrics {
+    pub calls_per_minute: u32,
+    // Tokens left in the bucket right now
+    pub remaining: f64,
+    pub high_priority_waiting: usize,
+    pub granted_high: u64,
+    pub granted_low: u64,
+    // Requests that had to wait for a token
+    pub throttled: u64,
+    pub total_wait_ms: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+// Token bucket shared by every task in the process. It holds a minute's worth of calls and
+// refills continuously, high priority requests are always served before low priority ones.
+pub struct RateLimiter {
+    calls_per_minute: u32,
+    bucket: Mutex<Bucket>,
+    high_waiting: AtomicUsize,
+    granted_high: AtomicU64,
+    granted_low: AtomicU64,
+    throttled: AtomicU64,
+    total_wait_ms: AtomicU64,
+}
+
+// Keeps the waiting count right even when the waiting request is cancelled
+struct HighWaiter<'a>(&'a AtomicUsize);
+
+impl Drop for HighWaiter<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl RateLimiter {
+    pub fn new(calls_per_minute: u32) -> Self {
+        let calls_per_minute = calls_per_minute.max(1);
+        Self {
+            calls_per_minute,
+            bucket: Mutex::new(Bucket {
+                tokens: calls_per_minute as f64,
+                refilled_at: Instant::now(),
+            }),
+            high_waiting: AtomicUsize::new(0),
+            granted_high: AtomicU64::new(0),
+            granted_low: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            total_wait_ms: AtomicU64::new(0),
+        }
+    }
+
+    // Reads <PREFIX>_PLAN (demo | pro) and <PREFIX>_CALLS_PER_MINUTE, which overrides the plan
+    pub fn from_env(prefix: &str) -> Self {
+        dotenv().ok();
+        let plan = env::var(format!("{}_PLAN", prefix))
+            .ok()
+            .and_then(|plan| ApiPlan::parse(&plan))
+            .unwrap_or(ApiPlan::Demo);
+        let calls_per_minute = env::var(format!("{}_CALLS_PER_MINUTE", prefix))
+            .ok()
+            .and_then(|calls| calls.parse::<u32>().ok())
+            .unwrap_or(plan.calls_per_minute());
+        Self::new(calls_per_minute)
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.calls_per_minute as f64 / 60.0
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.refill_per_sec()).min(self.calls_per_minute as f64);
+        bucket.refilled_at = now;
+    }
+
+    // Takes a token or returns how long to wait before trying again
+    fn try_take(&self, priority: Priority) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+
+        if priority == Priority::Low && self.high_waiting.load(Ordering::SeqCst) > 0 {
+            return Err(LOW_PRIORITY_POLL);
+        }
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.refill_per_sec(),
+        ))
+    }
+
+    pub async fn acquire(&self) {
+        self.acquire_with(current_priority()).await
+    }
+
+    pub async fn acquire_with(&self, priority: Priority) {
+        let _waiter = (priority == Priority::High).then(|| {
+            self.high_waiting.fetch_add(1, Ordering::SeqCst);
+            HighWaiter(&self.high_waiting)
+        });
+
+        let started = Instant::now();
+        let mut waited = false;
+        while let Err(wait) = self.try_take(priority) {
+            waited = true;
+            tokio::time::sleep(wait).await;
+        }
+
+        if waited {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            self.total_wait_ms
+                .fetch_add(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+        }
+        match priority {
+            Priority::High => self.granted_high.fetch_add(1, Ordering::Relaxed),
+            Priority::Low => self.granted_low.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn metrics(&self) -> RateLimitMetrics {
+        let remaining = {
+            let mut bucket = self.bucket.lock().unwrap();
+            self.refill(&mut bucket);
+            bucket.tokens
+        };
+        RateLimitMetrics {
+            calls_per_minute: self.calls_per_minute,
+            remaining: (remaining * 100.0).floor() / 100.0,
+            high_priority_waiting: self.high_waiting.load(Ordering::SeqCst),
+            granted_high: self.granted_high.load(Ordering::Relaxed),
+            granted_low: self.granted_low.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            total_wait_ms: self.total_wait_ms.load(Ordering::Relaxed),
+        }
+    }
+}
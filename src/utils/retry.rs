@@ -0,0 +1,230 @@
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Client, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::time::Duration;
+use std::{env, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    Transport,
+    Timeout,
+    RateLimited,
+    ServerError,
+    ClientError,
+    Decode,
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchError {
+    pub class: FailureClass,
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl FetchError {
+    pub fn from_status(status: StatusCode, retry_after: Option<Duration>) -> Self {
+        let class = match status {
+            StatusCode::TOO_MANY_REQUESTS => FailureClass::RateLimited,
+            StatusCode::REQUEST_TIMEOUT => FailureClass::Timeout,
+            s if s.is_server_error() => FailureClass::ServerError,
+            _ => FailureClass::ClientError,
+        };
+        Self {
+            class,
+            retry_after,
+            message: format!("HTTP {}", status),
+        }
+    }
+
+    pub fn from_reqwest(err: reqwest::Error) -> Self {
+        if let Some(status) = err.status() {
+            return Self::from_status(status, None);
+        }
+        let class = if err.is_timeout() {
+            FailureClass::Timeout
+        } else if err.is_decode() {
+            FailureClass::Decode
+        } else {
+            FailureClass::Transport
+        };
+        Self {
+            class,
+            retry_after: None,
+            message: err.to_string(),
+        }
+    }
+
+    // 4xx other than 408/429 and undecodable bodies will fail the same way again
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self.class, FailureClass::ClientError | FailureClass::Decode)
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.class, self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryError {
+    pub class: FailureClass,
+    pub attempts: u32,
+    pub last_error: FetchError,
+}
+
+impl fmt::Display for RetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Gave up after {} attempt(s) on {:?}: {}",
+            self.attempts, self.class, self.last_error.message
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Fraction of each delay that is randomised, 0.0 disables jitter
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Reads <PREFIX>_RETRY_MAX_ATTEMPTS, _BASE_DELAY_MS, _MAX_DELAY_MS and _JITTER
+    pub fn from_env(prefix: &str) -> Self {
+        Self::from_env_or(prefix, Self::default())
+    }
+
+    // Same variables, for callers whose defaults differ from a single HTTP request's
+    pub fn from_env_or(prefix: &str, defaults: Self) -> Self {
+        dotenv().ok();
+        let var = |name: &str| env::var(format!("{}_RETRY_{}", prefix, name)).ok();
+
+        Self {
+            max_attempts: var("MAX_ATTEMPTS")
+                .and_then(|v| v.parse::<u32>().ok())
+                .map(|v| v.max(1))
+                .unwrap_or(defaults.max_attempts),
+            base_delay: var("BASE_DELAY_MS")
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.base_delay),
+            max_delay: var("MAX_DELAY_MS")
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_delay),
+            jitter: var("JITTER")
+                .and_then(|v| v.parse::<f64>().ok())
+                .map(|v| v.clamp(0.0, 1.0))
+                .unwrap_or(defaults.jitter),
+        }
+    }
+
+    // Exponential backoff for the given (1-based) attempt, capped at max_delay
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    // A server supplied Retry-After wins over the computed backoff, up to max_delay
+    pub fn delay_for(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+        let backoff = self.backoff(attempt);
+        if self.jitter <= 0.0 {
+            return backoff;
+        }
+        let spread = rand::thread_rng().gen_range(0.0..self.jitter);
+        backoff.mul_f64(1.0 - spread)
+    }
+
+    pub async fn run<T, F, Fut>(&self, label: &str, mut operation: F) -> Result<T, RetryError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, FetchError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let err = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            if !err.is_retryable() || attempt >= self.max_attempts {
+                return Err(RetryError {
+                    class: err.class,
+                    attempts: attempt,
+                    last_error: err,
+                });
+            }
+
+            let delay = self.delay_for(attempt, err.retry_after);
+            println!(
+                "{} failed (Attempt {}): {}. Retrying in {:?}",
+                label, attempt, err, delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+// Accepts both forms allowed by RFC 9110: delay-seconds and an HTTP-date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&Utc) - Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+pub fn check_status(response: Response) -> Result<Response, FetchError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    Err(FetchError::from_status(status, retry_after))
+}
+
+// Single GET + JSON decode with every failure classified for the retry policy
+pub async fn get_json<T: DeserializeOwned>(client: &Client, url: &str) -> Result<T, FetchError> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(FetchError::from_reqwest)?;
+    let response = check_status(response)?;
+    response.json::<T>().await.map_err(|e| FetchError {
+        class: FailureClass::Decode,
+        retry_after: None,
+        message: e.to_string(),
+    })
+}
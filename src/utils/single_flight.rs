@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+// Collapses concurrent calls for the same key into one. Callers that arrive while a call is in
+// flight wait for it and share its result, the next call after it finishes runs again.
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub async fn run<F, Fut>(&self, key: K, operation: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let call = self
+            .calls
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        // If the caller running the operation is dropped, a waiting caller takes over
+        let value = call.get_or_init(operation).await.clone();
+
+        let mut calls = self.calls.lock().unwrap();
+        if calls
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &call))
+        {
+            calls.remove(&key);
+        }
+        value
+    }
+}
@@ -1,20 +1,39 @@
-use super::{calculate_transaction_amount, coingecko::COINGECKO_INSTANCE};
+use super::calculate_transaction_amount;
+use super::coingecko::CoinGecko;
+use super::currency::QuoteCurrency;
+use super::midgard::MidGard;
+use super::price_oracle::{
+    CoinGeckoOracle, CompositeOracle, OracleError, PriceOracle, ProviderKind,
+};
 use crate::{
-    db::MySQL,
-    models::actions_model::{SwapTransaction, SwapTransactionFromatted, TransactionData},
+    db::{MySQL, UpsertSummary},
+    models::{
+        actions_model::{
+            refund_total, PricingStatus, SwapOutcome, SwapQuoteValue, SwapRefundLeg, SwapStatus,
+            SwapStatusUpdate, SwapTransaction, SwapTransactionFromatted, SwapValuation,
+            TransactionData, ValuationLeg,
+        },
+        checkpoint_model::Checkpoint,
+    },
     utils::{
-        asset_name_from_pool, coin_name_from_pool, convert_nano_to_sec, convert_to_standard_unit,
-        format_epoch_timestamp, parse_f64,
+        asset::{short_asset_name, underlying_asset, Asset, AssetError, AssetKind},
+        convert_nano_to_sec, convert_to_standard_unit, format_epoch_timestamp, parse_amount,
+        parse_f64, price_to_decimal,
     },
 };
+use chrono::{TimeZone, Utc};
+use dotenv::dotenv;
+use futures_util::stream::{self, StreamExt};
 use reqwest::Error as ReqwestError;
+use rust_decimal::{Decimal, RoundingStrategy};
 use sqlx::Error as SqlxError;
-use std::fmt;
+use std::sync::Arc;
+use std::{env, fmt};
 
 #[derive(Debug)]
 pub enum TransactionError {
     MissingInCoin,
-    MissingAssetName,
+    InvalidAsset(String),
     CoinNotFound(String),
     PriceFetchError(String),
     MissingTxId,
@@ -31,7 +50,7 @@ impl fmt::Display for TransactionError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             TransactionError::MissingInCoin => write!(f, "Missing in_coin"),
-            TransactionError::MissingAssetName => write!(f, "Error parsing asset name"),
+            TransactionError::InvalidAsset(err) => write!(f, "Error parsing asset: {}", err),
             TransactionError::CoinNotFound(coin_name) => write!(f, "Coin not found: {}", coin_name),
             TransactionError::PriceFetchError(coin_name) => {
                 write!(f, "Price fetch failed for: {}", coin_name)
@@ -60,70 +79,389 @@ impl From<SqlxError> for TransactionError {
     }
 }
 
-pub struct TransactionHandler;
+// Where USD values come from. Midgard's inPriceUSD/outPriceUSD are the pool prices at the
+// swap's block, CoinGecko only has a daily price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValuationMode {
+    // Midgard prices only, legs without one fail to value
+    Midgard,
+    // Midgard prices, CoinGecko for legs Midgard has no price for
+    MidgardWithFallback,
+    // CoinGecko daily prices only
+    CoinGecko,
+    // Midgard prices, compared against CoinGecko and reported when they disagree
+    CrossCheck,
+}
+
+impl ValuationMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "midgard" => Some(ValuationMode::Midgard),
+            "midgard_fallback" => Some(ValuationMode::MidgardWithFallback),
+            "coingecko" => Some(ValuationMode::CoinGecko),
+            "cross_check" => Some(ValuationMode::CrossCheck),
+            _ => None,
+        }
+    }
+}
+
+impl ValuationMode {
+    // Provider order used when PRICE_PROVIDERS isn't set
+    pub fn default_providers(&self) -> Vec<ProviderKind> {
+        match self {
+            ValuationMode::Midgard | ValuationMode::CrossCheck => {
+                vec![ProviderKind::MidgardMetadata]
+            }
+            ValuationMode::MidgardWithFallback => {
+                vec![ProviderKind::MidgardMetadata, ProviderKind::CoinGecko]
+            }
+            ValuationMode::CoinGecko => vec![ProviderKind::CoinGecko],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ValuationConfig {
+    pub mode: ValuationMode,
+    // Price providers in the order they are tried
+    pub providers: Vec<ProviderKind>,
+    // Price file for the `file` provider
+    pub price_file: Option<String>,
+    // Relative difference above which a cross-check is reported
+    pub cross_check_tolerance: f64,
+    // Currencies every leg is also valued in, besides USD
+    pub currencies: Vec<QuoteCurrency>,
+    // Swaps of a page valued at once, price requests still queue on the providers' rate limits
+    pub concurrency: usize,
+}
+
+impl Default for ValuationConfig {
+    fn default() -> Self {
+        let mode = ValuationMode::MidgardWithFallback;
+        Self {
+            mode,
+            providers: mode.default_providers(),
+            price_file: None,
+            cross_check_tolerance: 0.05,
+            currencies: vec![
+                QuoteCurrency::Eur,
+                QuoteCurrency::Gbp,
+                QuoteCurrency::Btc,
+                QuoteCurrency::Rune,
+            ],
+            concurrency: 8,
+        }
+    }
+}
+
+impl ValuationConfig {
+    // Reads VALUATION_MODE (midgard | midgard_fallback | coingecko | cross_check),
+    // PRICE_PROVIDERS (comma separated: midgard, coingecko, coingecko_intraday, pool_depth,
+    // file), PRICE_FILE, VALUATION_CROSS_CHECK_TOLERANCE, VALUATION_CURRENCIES (comma
+    // separated: eur, gbp, btc, rune) and VALUATION_CONCURRENCY. An explicit provider list
+    // overrides the mode's order.
+    pub fn from_env() -> Result<Self, OracleError> {
+        dotenv().ok();
+        let defaults = Self::default();
+
+        let mode = env::var("VALUATION_MODE")
+            .ok()
+            .and_then(|mode| ValuationMode::parse(&mode))
+            .unwrap_or(defaults.mode);
+        let providers = match env::var("PRICE_PROVIDERS") {
+            Ok(providers) => ProviderKind::parse_list(&providers)?,
+            Err(_) => mode.default_providers(),
+        };
+
+        Ok(Self {
+            mode,
+            providers,
+            price_file: env::var("PRICE_FILE").ok(),
+            cross_check_tolerance: env::var("VALUATION_CROSS_CHECK_TOLERANCE")
+                .ok()
+                .and_then(|tolerance| tolerance.parse::<f64>().ok())
+                .unwrap_or(defaults.cross_check_tolerance),
+            currencies: match env::var("VALUATION_CURRENCIES") {
+                Ok(currencies) => QuoteCurrency::parse_list(&currencies)
+                    .map_err(|err| OracleError::Config(err.to_string()))?,
+                Err(_) => defaults.currencies,
+            },
+            concurrency: env::var("VALUATION_CONCURRENCY")
+                .ok()
+                .and_then(|concurrency| concurrency.parse::<usize>().ok())
+                .filter(|concurrency| *concurrency > 0)
+                .unwrap_or(defaults.concurrency),
+        })
+    }
+
+    // The setting that needs the CoinGecko client, if any does
+    pub fn coingecko_required_by(&self) -> Option<String> {
+        if let Some(kind) = self.providers.iter().find(|kind| kind.needs_coingecko()) {
+            return Some(format!(
+                "PRICE_PROVIDERS (or VALUATION_MODE) {}",
+                kind.as_str()
+            ));
+        }
+        if self.mode == ValuationMode::CrossCheck {
+            return Some("VALUATION_MODE cross_check".to_string());
+        }
+        self.currencies
+            .iter()
+            .find(|currency| **currency != QuoteCurrency::Usd && currency.pool_asset().is_none())
+            .map(|currency| format!("VALUATION_CURRENCIES {}", currency.as_str()))
+    }
+}
+
+// The CoinGecko client on the server's pool, built only when the configuration uses it. A
+// missing key is reported along with the setting that needs it.
+pub fn coingecko_client(
+    config: &ValuationConfig,
+    store: &MySQL,
+) -> Result<Option<Arc<CoinGecko>>, OracleError> {
+    let Some(requirement) = config.coingecko_required_by() else {
+        return Ok(None);
+    };
+    CoinGecko::init(Some(store.clone()))
+        .map(|coingecko| Some(Arc::new(coingecko)))
+        .map_err(|err| OracleError::Config(format!("{} needs CoinGecko: {}", requirement, err)))
+}
+
+// Kinds of swap that are stored, from SWAP_ASSET_KINDS (comma separated: native, synth, trade,
+// secured). All of them by default.
+pub fn swap_asset_kinds_from_env() -> Result<Vec<AssetKind>, AssetError> {
+    dotenv().ok();
+    match env::var("SWAP_ASSET_KINDS") {
+        Ok(kinds) => AssetKind::parse_list(&kinds),
+        Err(_) => Ok(AssetKind::ALL.to_vec()),
+    }
+}
+
+// The swap's own Midgard prices when it traded BTC or RUNE, otherwise the pool depths, which
+// price either at any height
+const QUOTE_RATE_PROVIDERS: [ProviderKind; 2] =
+    [ProviderKind::MidgardMetadata, ProviderKind::PoolDepth];
+
+// Parses and values swaps. Built once at startup, so configuration errors surface before any
+// job runs, and shared by every job.
+pub struct TransactionHandler {
+    config: ValuationConfig,
+    oracle: CompositeOracle,
+    // BTC and RUNE rates of the quote currencies, whatever providers value the legs
+    quote_oracle: CompositeOracle,
+    // Reference prices for the cross-check, and fiat rates for quote currencies
+    coingecko: Option<Arc<CoinGecko>>,
+    asset_kinds: Vec<AssetKind>,
+}
 
 impl TransactionHandler {
-    pub async fn parse_data(
-        &self,
+    pub fn currencies(&self) -> &[QuoteCurrency] {
+        &self.config.currencies
+    }
+
+    pub fn new(
+        config: ValuationConfig,
+        asset_kinds: Vec<AssetKind>,
+        midgard: &MidGard,
+        coingecko: Option<Arc<CoinGecko>>,
+    ) -> Result<Self, OracleError> {
+        let oracle = CompositeOracle::build(
+            &config.providers,
+            config.price_file.as_deref(),
+            midgard,
+            coingecko.as_ref(),
+        )?;
+        let quote_oracle =
+            CompositeOracle::build(&QUOTE_RATE_PROVIDERS, None, midgard, coingecko.as_ref())?;
+        Ok(Self {
+            config,
+            oracle,
+            quote_oracle,
+            coingecko,
+            asset_kinds,
+        })
+    }
+
+    // Asset, base-unit amount and address of a leg
+    pub fn parse_leg(
         info: &TransactionData,
-        swap_date: &str,
-    ) -> Result<(String, f64, f64, String), TransactionError> {
-        let in_coin = info.coins.get(0).ok_or(TransactionError::MissingInCoin)?;
-        let coin_name =
-            coin_name_from_pool(&in_coin.asset).ok_or(TransactionError::MissingAssetName)?;
+    ) -> Result<(String, Decimal, String), TransactionError> {
+        let in_coin = info.coins.first().ok_or(TransactionError::MissingInCoin)?;
 
-        let in_amount = parse_f64(&in_coin.amount).expect("Floating point parse error");
+        let in_amount = parse_amount(&in_coin.amount).map_err(|err| {
+            TransactionError::ProcessingError(format!("Invalid amount {}: {}", in_coin.amount, err))
+        })?;
 
-        let in_amount_usd = self
-            .convert_amount_to_usd(&coin_name, swap_date, in_amount)
-            .await?;
+        let in_asset = Asset::parse(&in_coin.asset)
+            .map_err(|err| TransactionError::InvalidAsset(err.to_string()))?
+            .to_string();
+
+        Ok((in_asset, in_amount, info.address.clone()))
+    }
+
+    // A leg that can't be priced keeps its raw amount and no valuation, the repricing job
+    // values it later
+    pub async fn parse_data(
+        &self,
+        swap: &SwapTransaction,
+        info: &TransactionData,
+        timestamp: i64,
+        leg: ValuationLeg,
+    ) -> Result<(String, Decimal, Option<SwapValuation>, String), TransactionError> {
+        let (in_asset, in_amount, in_address) = Self::parse_leg(info)?;
 
-        let in_asset =
-            asset_name_from_pool(&in_coin.asset).ok_or(TransactionError::MissingAssetName)?;
-        let in_address = info.address.clone();
+        let valuation = self
+            .value_in_usd(swap, &in_asset, timestamp, in_amount, leg)
+            .await
+            .ok();
 
-        Ok((in_asset, in_amount, in_amount_usd, in_address))
+        Ok((in_asset, in_amount, valuation, in_address))
     }
-    pub async fn convert_amount_to_usd(
+
+    pub async fn parse_optional_data(
         &self,
-        asset_name: &str,
-        date: &str,
-        amount: f64,
-    ) -> Result<f64, TransactionError> {
-        let mut coingecko = COINGECKO_INSTANCE.write().await;
-
-        let coin_id = match coingecko.get_coin_id(&asset_name) {
-            Some(coin_id) => coin_id,
-            None => {
-                let coin_id = coingecko.search_coin(asset_name).await.map_err(|_| {
-                    println!("Error searching for coin ID for asset: {}", asset_name);
-                    TransactionError::CoinNotFound(asset_name.to_string())
-                })?;
-                let coin_id  =coin_id.ok_or_else(|| {
-                    println!("Coin ID not found after search for asset: {}", asset_name);
-                    TransactionError::CoinNotFound(asset_name.to_string())
-                })?;
-                coingecko.add_coin_id(&asset_name, &coin_id);
-                coin_id
+        swap: &SwapTransaction,
+        info: Option<&TransactionData>,
+        timestamp: i64,
+        leg: ValuationLeg,
+    ) -> Result<
+        (
+            Option<String>,
+            Option<Decimal>,
+            Option<SwapValuation>,
+            Option<String>,
+        ),
+        TransactionError,
+    > {
+        match info {
+            Some(info) => {
+                let (asset, amount, valuation, address) =
+                    self.parse_data(swap, info, timestamp, leg).await?;
+                Ok((Some(asset), Some(amount), valuation, Some(address)))
             }
-        };
-        let price_on_date = coingecko
-            .fetch_usd_price(coin_id.as_str(), date)
+            None => Ok((None, None, None, None)),
+        }
+    }
+
+    // Values a base-unit amount of an asset with the configured price providers, along with
+    // the quote the value was computed from. Synth, trade and secured assets are priced as the
+    // L1 asset they are backed by, and Midgard prices come from the swap itself.
+    pub async fn value_in_usd(
+        &self,
+        swap: &SwapTransaction,
+        asset: &str,
+        timestamp: i64,
+        amount: Decimal,
+        leg: ValuationLeg,
+    ) -> Result<SwapValuation, TransactionError> {
+        let pool_asset = underlying_asset(asset);
+        let pool_asset = pool_asset.as_str();
+        let quote = self
+            .oracle
+            .price_for(Some(swap), pool_asset, timestamp)
             .await
-            .map_err(|_| {
+            .map_err(|err| {
                 println!(
-                    "Error fetching price for coin ID: {} on date: {}",
-                    coin_id, date
+                    "Error pricing asset: {} at {} ({})",
+                    pool_asset, timestamp, err
                 );
-                TransactionError::PriceFetchError(coin_id.clone())
+                match err {
+                    OracleError::Unsupported(asset) => TransactionError::CoinNotFound(asset),
+                    _ => TransactionError::PriceFetchError(pool_asset.to_string()),
+                }
             })?;
-        let amount = convert_to_standard_unit(amount, 8);
-        let t_amount = calculate_transaction_amount(amount, price_on_date);
+        let unit_price_usd = price_to_decimal(quote.price_usd)
+            .ok_or_else(|| TransactionError::PriceFetchError(pool_asset.to_string()))?;
+        let amount_usd = usd_amount(amount, unit_price_usd);
 
-        Ok((t_amount * 100.0).round() / 100.0)
+        let cross_check = self.coingecko.as_ref().filter(|_| {
+            self.config.mode == ValuationMode::CrossCheck && quote.provider != "coingecko"
+        });
+        if let Some(coingecko) = cross_check {
+            let reference = CoinGeckoOracle::new(coingecko.clone());
+            match reference.price_usd(pool_asset, timestamp).await {
+                Ok(reference) => {
+                    let deviation = relative_deviation(quote.price_usd, reference.price_usd);
+                    if deviation > self.config.cross_check_tolerance {
+                        println!(
+                            "Price cross-check for {} at {}: {} {} vs CoinGecko {} ({:.1}% apart)",
+                            pool_asset,
+                            timestamp,
+                            quote.provider,
+                            quote.price_usd,
+                            reference.price_usd,
+                            deviation * 100.0
+                        );
+                    }
+                }
+                Err(err) => println!("Price cross-check skipped for {}: {}", pool_asset, err),
+            }
+        }
+
+        Ok(SwapValuation {
+            tx_id: String::new(),
+            leg_key: String::new(),
+            leg: leg.as_str().to_string(),
+            asset: asset.to_string(),
+            provider: quote.provider.to_string(),
+            coin_id: quote.coin_id,
+            unit_price_usd,
+            amount_usd,
+            price_timestamp: quote.timestamp,
+            swap_timestamp: timestamp,
+            confidence: quote.confidence.as_str().to_string(),
+        })
+    }
+
+    // USD per unit of each configured quote currency at the timestamp. Crypto currencies come
+    // from their own provider chain, so they don't depend on the providers configured for the
+    // legs, fiat from CoinGecko's daily rates. A currency without a rate is skipped, which
+    // leaves the swap unpriced so the repricing job fetches the rate again.
+    pub async fn quote_rates(
+        &self,
+        swap: &SwapTransaction,
+        timestamp: i64,
+    ) -> Vec<(QuoteCurrency, Decimal)> {
+        let mut rates = Vec::with_capacity(self.config.currencies.len());
+        for currency in &self.config.currencies {
+            let rate = match currency.pool_asset() {
+                Some(asset) => self
+                    .quote_oracle
+                    .price_for(Some(swap), asset, timestamp)
+                    .await
+                    .map(|quote| quote.price_usd)
+                    .map_err(|err| err.to_string()),
+                None => {
+                    let date = Utc
+                        .timestamp_opt(timestamp, 0)
+                        .single()
+                        .map(|time| time.format("%d-%m-%Y").to_string())
+                        .unwrap_or_default();
+                    match &self.coingecko {
+                        Some(coingecko) => coingecko
+                            .fetch_fiat_usd_rate(*currency, &date)
+                            .await
+                            .map_err(|err| err.to_string()),
+                        None => Err("no CoinGecko client".to_string()),
+                    }
+                }
+            };
+            match rate.and_then(|rate| {
+                price_to_decimal(rate).ok_or_else(|| format!("Invalid rate {}", rate))
+            }) {
+                Ok(rate) => rates.push((*currency, rate)),
+                Err(err) => println!(
+                    "No {} rate at {}, skipping its values: {}",
+                    currency.as_str(),
+                    timestamp,
+                    err
+                ),
+            }
+        }
+        rates
     }
 
     pub async fn parse_transaction(
+        &self,
         swap: &SwapTransaction,
     ) -> Result<SwapTransactionFromatted, TransactionError> {
         // Parse swap_date & swap_time
@@ -135,80 +473,281 @@ impl TransactionHandler {
         // Parse tx_id from in_data
         let tx_id = swap
             .in_data
-            .get(0)
+            .first()
             .and_then(|data| data.txID.clone())
             .ok_or(TransactionError::MissingTxId)?;
 
-        let handler = TransactionHandler;
-
         // Parse In Data
-        let in_data = swap.in_data.get(0).ok_or(TransactionError::MissingInData)?;
-        let (in_asset, in_amount, in_amount_usd, in_address) =
-            handler.parse_data(in_data, &swap_date).await?;
+        let in_data = swap
+            .in_data
+            .first()
+            .ok_or(TransactionError::MissingInData)?;
+        let (in_asset, in_amount, in_valuation, in_address) = self
+            .parse_data(swap, in_data, epoc_timestamp, ValuationLeg::In)
+            .await?;
 
-        let mut out_data = swap.out_data.clone();
-        out_data.reverse();
+        // Parse Out Data, delivered outputs and refunded amounts are valued separately. A pending
+        // swap may not have paid out yet, it is stored without out legs until it settles.
+        let (delivered, refunds) = swap.split_out_legs();
+        if delivered.is_empty() && refunds.is_empty() && SwapStatus::of(swap) != SwapStatus::Pending
+        {
+            return Err(TransactionError::MissingOutData);
+        }
 
-        // Parse Out Data
-        let out_data_1 = out_data.get(0).ok_or(TransactionError::MissingOutData)?;
-        let (out_asset_1, out_amount_1, out_amount_1_usd, out_address_1) =
-            handler.parse_data(out_data_1, &swap_date).await?;
+        let (out_asset_1, out_amount_1, out_1_valuation, out_address_1) = self
+            .parse_optional_data(
+                swap,
+                delivered.first().copied(),
+                epoc_timestamp,
+                ValuationLeg::Out1,
+            )
+            .await?;
+        let (out_asset_2, out_amount_2, out_2_valuation, out_address_2) = self
+            .parse_optional_data(
+                swap,
+                delivered.get(1).copied(),
+                epoc_timestamp,
+                ValuationLeg::Out2,
+            )
+            .await?;
 
-        let (out_asset_2, out_amount_2, out_amount_2_usd, out_address_2) = if let Some(val) =
-            out_data.get(1)
-        {
-            let (asset, amount, amount_usd, address) = handler.parse_data(val, &swap_date).await?;
-            (Some(asset), Some(amount), Some(amount_usd), Some(address))
-        } else {
-            (None, None, None, None)
-        };
+        // Every refund leg is kept, the refund columns hold their total
+        let mut refund_legs = Vec::with_capacity(refunds.len());
+        for (index, info) in refunds.iter().enumerate() {
+            let (asset, amount, address) = Self::parse_leg(info)?;
+            refund_legs.push(SwapRefundLeg {
+                tx_id: tx_id.clone(),
+                leg_key: String::new(),
+                leg_index: index as i32,
+                asset,
+                amount,
+                address,
+            });
+        }
+        let (refund_asset, refund_amount, refund_valuation, refund_address) =
+            match refund_total(&refund_legs) {
+                Some((first, amount)) => {
+                    if refund_legs.iter().any(|leg| leg.asset != first.asset) {
+                        println!(
+                            "Refund of {} pays out more than one asset, only {} is valued",
+                            tx_id, first.asset
+                        );
+                    }
+                    let valuation = self
+                        .value_in_usd(
+                            swap,
+                            &first.asset,
+                            epoc_timestamp,
+                            amount,
+                            ValuationLeg::Refund,
+                        )
+                        .await
+                        .ok();
+                    (
+                        Some(first.asset.clone()),
+                        Some(amount),
+                        valuation,
+                        Some(first.address.clone()),
+                    )
+                }
+                None => (None, None, None, None),
+            };
+        let refund_reason = swap
+            .metadata
+            .refund
+            .as_ref()
+            .map(|refund| refund.reason.clone());
 
-        Ok(SwapTransactionFromatted {
+        let mut record = SwapTransactionFromatted {
             timestamp: epoc_timestamp,
             date: swap_date,
             time: swap_time,
-            in_asset,
+            in_asset: short_asset_name(&in_asset),
             in_amount,
-            in_amount_usd,
-            out_asset_1,
+            in_amount_usd: in_valuation.as_ref().map(|valuation| valuation.amount_usd),
+            out_asset_1: out_asset_1.as_deref().map(short_asset_name),
             out_amount_1,
-            out_amount_1_usd,
+            out_amount_1_usd: out_1_valuation
+                .as_ref()
+                .map(|valuation| valuation.amount_usd),
             in_address,
             out_address_1,
             tx_id,
-            out_asset_2,
+            out_asset_2: out_asset_2.as_deref().map(short_asset_name),
             out_amount_2,
-            out_amount_2_usd,
+            out_amount_2_usd: out_2_valuation
+                .as_ref()
+                .map(|valuation| valuation.amount_usd),
             out_address_2,
+            status: SwapStatus::of(swap).as_str().to_string(),
+            outcome: SwapOutcome::of(swap).as_str().to_string(),
+            refund_asset: refund_asset.as_deref().map(short_asset_name),
+            refund_amount,
+            refund_amount_usd: refund_valuation
+                .as_ref()
+                .map(|valuation| valuation.amount_usd),
+            refund_address,
+            refund_reason,
+            pricing_status: String::new(),
+            asset_kind: swap.asset_kind().as_str().to_string(),
+            in_asset_canonical: Some(in_asset),
+            out_asset_1_canonical: out_asset_1,
+            out_asset_2_canonical: out_asset_2,
+            refund_asset_canonical: refund_asset,
+            streaming_count: None,
+            streaming_quantity: None,
+            streaming_interval: None,
+            streaming_last_height: None,
+            streaming_deposited_amount: None,
+            streaming_in_amount: None,
+            streaming_out_amount: None,
+            streaming_fill_percent: None,
+            streaming_duration_secs: None,
+            status_history: Vec::new(),
+            valuations: Vec::new(),
+            refund_legs: Vec::new(),
+            quote_values: Vec::new(),
+        };
+        record.set_streaming_details(swap.streaming_details().as_ref());
+        // Valuations are keyed by the row they belong to, known only once it is built
+        let leg_key = record.leg_key();
+        record.valuations = [
+            in_valuation,
+            out_1_valuation,
+            out_2_valuation,
+            refund_valuation,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|valuation| SwapValuation {
+            tx_id: record.tx_id.clone(),
+            leg_key: leg_key.clone(),
+            ..valuation
         })
+        .collect();
+        record.refund_legs = refund_legs
+            .into_iter()
+            .map(|leg| SwapRefundLeg {
+                leg_key: leg_key.clone(),
+                ..leg
+            })
+            .collect();
+        if !record.valuations.is_empty() {
+            let rates = self.quote_rates(swap, epoc_timestamp).await;
+            record.quote_values = quote_values(&record.valuations, &rates);
+        }
+        let pricing_status = PricingStatus::of(&record, &self.config.currencies);
+        if pricing_status != PricingStatus::Priced {
+            println!(
+                "Storing {} with {} unpriced leg(s) and {} missing quote value(s), queued for repricing",
+                record.tx_id,
+                record.missing_usd_values(),
+                record.missing_quote_values(&self.config.currencies)
+            );
+        }
+        record.pricing_status = pricing_status.as_str().to_string();
+        Ok(record)
     }
 
-    pub async fn process_and_insert_transaction(
-        mysql: &MySQL,
-        actions: &Vec<SwapTransaction>,
-    ) -> Result<(), TransactionError> {
-        
+    // Splits a page into storable swaps and the status of every inbound tx it mentions.
+    // Pending swaps have no settled outputs yet, they are only queued for reconciliation.
+    // Refunds of swaps are stored like swaps, with the returned funds in the refund columns.
+    pub async fn parse_page(
+        &self,
+        actions: &[SwapTransaction],
+    ) -> (Vec<SwapTransactionFromatted>, Vec<SwapStatusUpdate>) {
+        let mut swaps = Vec::with_capacity(actions.len());
+        let mut statuses = Vec::with_capacity(actions.len());
+
         for swap in actions {
-            if swap.status != "success" {
-                println!("Transaction Pending");
+            if !swap.is_swap() {
+                println!("Skipping refund of a non-swap tx");
+                continue;
+            }
+            let kind = swap.asset_kind();
+            if !self.asset_kinds.contains(&kind) {
+                println!("Skipping {} swap, kind not configured", kind.as_str());
                 continue;
             }
-            let transaction_info = TransactionHandler::parse_transaction(&swap).await;
+            let status = SwapStatus::of(swap);
+            if let Some(tx_id) = swap.in_data.first().and_then(|data| data.txID.clone()) {
+                statuses.push(SwapStatusUpdate { tx_id, status });
+            }
+            swaps.push(swap);
+        }
 
-            let transaction_info = match transaction_info {
-                Ok(val) => val,
-                Err(err) => {
-                    println!("Error parsing transaction: {:?}", err);
-                    continue;
-                }
-            };
-            if let Err(err) = mysql.insert_new_record(transaction_info.clone()).await {
-                println!("Error during insertion: {:?}", err);
-            } else {
-                println!("Insertion Successful for Id : {}", &transaction_info.tx_id);
+        // Swaps are valued concurrently and come back in page order. The futures are built
+        // up front, as a mapping closure inside the stream would make the job futures not Send.
+        let parsing: Vec<_> = swaps
+            .into_iter()
+            .map(|swap| self.parse_transaction(swap))
+            .collect();
+        let parsed: Vec<_> = stream::iter(parsing)
+            .buffered(self.config.concurrency)
+            .collect()
+            .await;
+        let mut records = Vec::with_capacity(parsed.len());
+        for result in parsed {
+            match result {
+                Ok(val) => records.push(val),
+                Err(err) => println!("Error parsing transaction: {:?}", err),
             }
         }
 
-        Ok(())
+        (records, statuses)
+    }
+
+    // Parses a page of actions and hands it to the database as one batch, so the rows and the
+    // advanced checkpoint are committed together or not at all
+    pub async fn process_and_insert_transaction(
+        &self,
+        mysql: &MySQL,
+        actions: &[SwapTransaction],
+        checkpoint: Option<&Checkpoint<'_>>,
+    ) -> Result<UpsertSummary, TransactionError> {
+        let (records, statuses) = self.parse_page(actions).await;
+
+        let summary = mysql.store_page(&records, &statuses, checkpoint).await?;
+        println!(
+            "Page stored: {} inserted, {} updated, {} skipped",
+            summary.inserted, summary.updated, summary.skipped
+        );
+        Ok(summary)
+    }
+}
+
+// Every valued leg converted into every currency with a rate
+pub fn quote_values(
+    valuations: &[SwapValuation],
+    rates: &[(QuoteCurrency, Decimal)],
+) -> Vec<SwapQuoteValue> {
+    valuations
+        .iter()
+        .flat_map(|valuation| {
+            rates.iter().filter_map(move |(currency, rate)| {
+                Some(SwapQuoteValue {
+                    tx_id: valuation.tx_id.clone(),
+                    leg_key: valuation.leg_key.clone(),
+                    leg: valuation.leg.clone(),
+                    currency: currency.as_str().to_string(),
+                    amount: currency.convert_usd(valuation.amount_usd, *rate)?,
+                    usd_rate: *rate,
+                })
+            })
+        })
+        .collect()
+}
+
+// USD value of a base-unit amount, rounded once to cents
+pub fn usd_amount(amount: Decimal, price: Decimal) -> Decimal {
+    let amount = convert_to_standard_unit(amount, 8);
+    calculate_transaction_amount(amount, price)
+        .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+pub fn relative_deviation(value: f64, reference: f64) -> f64 {
+    if reference == 0.0 {
+        return if value == 0.0 { 0.0 } else { f64::INFINITY };
     }
+    ((value - reference) / reference).abs()
 }